@@ -1,5 +1,5 @@
 use criterion::{Criterion, criterion_group, criterion_main};
-use regex_engine::do_matching;
+use regex_engine::{Engine, Regex, do_matching};
 use std::time::Duration;
 
 /// (計測のid, a?^n a^nという正規表現、文字列)というタプル。
@@ -33,5 +33,22 @@ fn width_first(c: &mut Criterion) {
     }
 }
 
-criterion_group!(benches, width_first, depth_first);
+fn backtrack(c: &mut Criterion) {
+    let mut g = c.benchmark_group("Backtrack");
+    g.measurement_time(Duration::from_secs(12));
+
+    for i in INPUTS {
+        g.bench_with_input(i.0, &(i.1, i.2), |b, args| {
+            b.iter(|| {
+                Regex::new(args.0)
+                    .unwrap()
+                    .engine(Engine::Backtrack)
+                    .unwrap()
+                    .is_match_at_start(args.1)
+            })
+        });
+    }
+}
+
+criterion_group!(benches, width_first, depth_first, backtrack);
 criterion_main!(benches);
@@ -0,0 +1,110 @@
+#!/usr/bin/env perl
+# Generates src/engine/unicode_tables.rs from the Unicode Character Database bundled with Perl.
+#
+# Usage: perl scripts/unicode_tables.pl | rustfmt --edition 2024 > src/engine/unicode_tables.rs
+use strict;
+use warnings;
+use Unicode::UCD qw(all_casefolds prop_invlist prop_value_aliases prop_values);
+
+# Converts an inversion list to inclusive (start, end) ranges of Unicode scalar values.
+sub ranges {
+    my @list = @_;
+    my @ranges;
+    for (my $i = 0; $i < @list; $i += 2) {
+        my $start = $list[$i];
+        my $end = $i + 1 < @list ? $list[$i + 1] - 1 : 0x10FFFF;
+        # Surrogates are not scalar values.
+        if ($start <= 0xDFFF && $end >= 0xD800) {
+            push @ranges, [$start, 0xD7FF] if $start < 0xD800;
+            push @ranges, [0xE000, $end] if $end > 0xDFFF;
+        } else {
+            push @ranges, [$start, $end];
+        }
+    }
+    return @ranges;
+}
+
+sub rust_char {
+    my ($c) = @_;
+    return sprintf("'%s'", chr($c)) if $c >= 0x21 && $c <= 0x7E && $c != 0x27 && $c != 0x5C;
+    return sprintf("'\\u{%X}'", $c);
+}
+
+sub table {
+    my ($name, $prop) = @_;
+    my @ranges = ranges(prop_invlist($prop));
+    die "unknown property: $prop\n" unless @ranges;
+    print "pub const $name: &[(char, char)] = &[\n";
+    for my $r (@ranges) {
+        printf "    (%s, %s),\n", rust_char($r->[0]), rust_char($r->[1]);
+    }
+    print "];\n";
+}
+
+# Prints every value of a property with its aliases and ranges.
+# Values without any scalar value, such as the surrogate category Cs, are omitted.
+sub property_table {
+    my ($name, $prop) = @_;
+    print "#[cfg(feature = \"unicode-tables\")]\n";
+    print "pub const $name: PropertyTable = &[\n";
+    for my $value (sort { lc($a) cmp lc($b) } prop_values($prop)) {
+        my @ranges = ranges(prop_invlist("$prop=$value"));
+        next unless @ranges;
+        my %seen;
+        my @aliases = grep { !$seen{$_}++ } prop_value_aliases($prop, $value);
+        printf "    (\n        &[%s],\n        &[\n", join(", ", map { "\"$_\"" } @aliases);
+        for my $r (@ranges) {
+            printf "            (%s, %s),\n", rust_char($r->[0]), rust_char($r->[1]);
+        }
+        print "        ],\n    ),\n";
+    }
+    print "];\n";
+}
+
+printf "//! Unicode character tables generated by scripts/unicode_tables.pl from Unicode %s.\n",
+    Unicode::UCD::UnicodeVersion();
+print "//!\n//! Do not edit this file by hand.\n\n";
+print "/// General_Category=Decimal_Number, used for `\\d`.\n";
+table("DECIMAL_NUMBER", "gc=Nd");
+print "\n/// Word characters (Alphabetic, Mark, Decimal_Number, Connector_Punctuation and Join_Control),\n";
+print "/// used for `\\w` and `\\b`.\n";
+table("WORD", "Word");
+print "\n/// White_Space, used for `\\s`.\n";
+table("WHITE_SPACE", "White_Space");
+
+# Prints, for every character with a simple case folding, the other characters folding to the same one.
+sub case_folding_table {
+    my ($name) = @_;
+    my $folds = all_casefolds();
+    my %orbits;
+    for my $entry (values %$folds) {
+        next unless $entry->{status} =~ /^[CS]$/;
+        my $fold = hex($entry->{mapping});
+        push @{$orbits{$fold}}, hex($entry->{code});
+    }
+    my %others;
+    for my $fold (keys %orbits) {
+        my @orbit = ($fold, @{$orbits{$fold}});
+        for my $c (@orbit) {
+            $others{$c} = [sort { $a <=> $b } grep { $_ != $c } @orbit];
+        }
+    }
+    print "pub const $name: &[(char, &[char])] = &[\n";
+    for my $c (sort { $a <=> $b } keys %others) {
+        printf "    (%s, &[%s]),\n", rust_char($c), join(", ", map { rust_char($_) } @{$others{$c}});
+    }
+    print "];\n";
+}
+
+print "\n/// Characters that are equivalent under simple case folding, sorted by the first element.\n";
+case_folding_table("CASE_FOLDING_SIMPLE");
+
+print "\n/// Property values, each of which is a list of aliases and the ranges of the value.\n";
+print "#[cfg(feature = \"unicode-tables\")]\n";
+print "pub type PropertyTable = &'static [(&'static [&'static str], &'static [(char, char)])];\n";
+
+print "\n/// General_Category values and their aliases, used for `\\p{..}`.\n";
+property_table("GENERAL_CATEGORY", "gc");
+
+print "\n/// Script values and their aliases, used for `\\p{..}`.\n";
+property_table("SCRIPT", "sc");
@@ -1,17 +1,68 @@
+mod class;
 mod codegen;
+mod dfa;
 mod evaluator;
+mod lazy_dfa;
+mod onepass;
 mod parser;
+mod regex;
+mod unicode_tables;
 
 use crate::helper::DynError;
+use class::CharClass;
+pub use evaluator::Engine;
+use parser::Look;
+pub use regex::{BytesMatch, BytesRegex, Captures, Match, Regex, RegexBuilder};
 use std::fmt::{self, Display};
 
 #[derive(Debug)]
 pub enum Instruction {
     Char(char),
+    /// 改行を含む任意の1文字
     Any,
+    /// 改行以外の任意の1文字
+    AnyNotNewline,
+    Class(CharClass),
+    /// バイト単位の入力で、1バイトにマッチ
+    Byte(u8),
+    /// バイト単位の入力で、範囲内の1バイトにマッチ
+    ByteRange(u8, u8),
+    AssertStart,
+    AssertEnd,
+    AssertStartLine,
+    AssertEndLine,
+    WordBoundary {
+        ascii: bool,
+    },
+    NotWordBoundary {
+        ascii: bool,
+    },
     Match,
     Jump(usize),
     Split(usize, usize),
+    /// 現在のspをn番目のスロットに保存
+    Save(usize),
+    /// n番目のスロットに保存した位置から入力を消費していない場合は失敗
+    Progress(usize),
+    /// group番目のキャプチャグループにマッチした文字列と同じ文字列にマッチ
+    Backref {
+        group: usize,
+        case_insensitive: bool,
+        ascii: bool,
+    },
+    /// 次の命令からmatch命令までの部分プログラムで先読み・後読みを行い、成功した場合はnextへ進む
+    Look {
+        look: Look,
+        next: usize,
+    },
+    /// 次の命令からcut命令までの部分プログラムの最初のマッチのみを採用し、
+    /// slot番目のスロットに保存された終了位置からnextへ進む
+    Atomic {
+        slot: usize,
+        next: usize,
+    },
+    /// atomic命令の部分プログラムの終わり
+    Cut,
 }
 
 impl Display for Instruction {
@@ -19,9 +70,56 @@ impl Display for Instruction {
         match self {
             Instruction::Char(c) => write!(f, "char {}", c),
             Instruction::Any => write!(f, "any"),
+            Instruction::AnyNotNewline => write!(f, "any_not_newline"),
+            Instruction::Class(class) => write!(f, "class {}", class),
+            Instruction::Byte(b) => write!(f, "byte 0x{:02X}", b),
+            Instruction::ByteRange(lo, hi) => write!(f, "byte_range 0x{:02X}..=0x{:02X}", lo, hi),
+            Instruction::AssertStart => write!(f, "assert_start"),
+            Instruction::AssertEnd => write!(f, "assert_end"),
+            Instruction::AssertStartLine => write!(f, "assert_start_line"),
+            Instruction::AssertEndLine => write!(f, "assert_end_line"),
+            Instruction::WordBoundary { ascii } => {
+                write!(f, "word_boundary{}", if *ascii { " ascii" } else { "" })
+            }
+            Instruction::NotWordBoundary { ascii } => {
+                write!(f, "not_word_boundary{}", if *ascii { " ascii" } else { "" })
+            }
             Instruction::Match => write!(f, "match"),
             Instruction::Jump(addr) => write!(f, "jump {:>04}", addr),
             Instruction::Split(addr1, addr2) => write!(f, "split {:>04}, {:>04}", addr1, addr2),
+            Instruction::Save(n) => write!(f, "save {}", n),
+            Instruction::Progress(n) => write!(f, "progress {}", n),
+            Instruction::Backref {
+                group,
+                case_insensitive,
+                ascii,
+            } => {
+                write!(f, "backref {}", group)?;
+                if *case_insensitive {
+                    write!(f, " ignore_case{}", if *ascii { " ascii" } else { "" })?;
+                }
+                Ok(())
+            }
+            Instruction::Atomic { slot, next } => write!(f, "atomic {:>04}, {}", next, slot),
+            Instruction::Cut => write!(f, "cut"),
+            Instruction::Look { look, next } => match look {
+                Look::Ahead { negated } => {
+                    let name = if *negated {
+                        "not_look_ahead"
+                    } else {
+                        "look_ahead"
+                    };
+                    write!(f, "{} {:>04}", name, next)
+                }
+                Look::Behind { negated, min, max } => {
+                    let name = if *negated {
+                        "not_look_behind"
+                    } else {
+                        "look_behind"
+                    };
+                    write!(f, "{} {}..={}, {:>04}", name, min, max, next)
+                }
+            },
         }
     }
 }
@@ -40,17 +138,7 @@ impl Display for Instruction {
 ///
 /// 入力された正規表現にエラーがあったり、内部的な実装エラーがある場合はErrを返す。
 pub fn print(expr: &str) -> Result<(), DynError> {
-    println!("expr: {}\nAST:", expr);
-    let ast = parser::parse(expr)?;
-    println!("{}", ast);
-
-    println!();
-    println!("code:");
-    let code = codegen::get_code(&ast)?;
-    for (n, c) in code.iter().enumerate() {
-        println!("{:>04}: {}", n, c);
-    }
-
+    Regex::new(expr)?.print();
     Ok(())
 }
 
@@ -67,6 +155,7 @@ pub fn print(expr: &str) -> Result<(), DynError> {
 ///
 /// exprに正規表現、lineにマッチ対象とする文字列を与える。
 /// is_depthがtrueの場合は深さ優先探索を、falseの場合は幅優先探索を利用
+/// その他の評価器を利用する場合は[`Regex::engine`]を利用すること。
 ///
 ///
 /// # 返り値
@@ -75,9 +164,48 @@ pub fn print(expr: &str) -> Result<(), DynError> {
 /// エラーなく実行でき、かつマッチングに**失敗**した場合はOk(false)を返す。
 ///
 /// 入力された正規表現にエラーがあったり、内部的な実装エラーがある場合はErrを返す。
+///
+/// 同じ正規表現で何度もマッチングを行う場合は、パースとコード生成を一度だけ行う
+/// [`Regex`]を利用すること。
 pub fn do_matching(expr: &str, line: &str, is_depth: bool) -> Result<bool, DynError> {
-    let ast = parser::parse(expr)?;
-    let code = codegen::get_code(&ast)?;
+    let ast = parser::parse(expr, parser::Flags::default())?;
+    let code = codegen::get_code(&ast, codegen::DEFAULT_SIZE_LIMIT)?;
     let line: Vec<char> = line.chars().collect();
-    Ok(evaluator::eval(&code, &line, is_depth)?)
+    let engine = if is_depth {
+        Engine::DepthFirst
+    } else {
+        Engine::WidthFirst
+    };
+    Ok(evaluator::eval(&code, &line, engine)?)
+}
+
+/// 正規表現とバイト列をマッチング。
+///
+/// [`do_matching`]と同様に先頭からマッチングを行うが、lineを文字に復号せず、
+/// 正規表現をUTF-8のバイト列にマッチするコードに変換して評価する。
+/// 不正なUTF-8を含むlineも扱える。
+///
+/// # 利用例
+///
+/// ```
+/// use regex_engine;
+/// assert!(regex_engine::do_matching_bytes("\\w+", b"caf\xC3\xA9\xFF", true).unwrap());
+/// assert!(!regex_engine::do_matching_bytes(".", b"\xFF", true).unwrap());
+/// ```
+///
+/// # 返り値
+///
+/// エラーなく実行でき、かつマッチングに**成功**した場合はOk(true)を返し、
+/// エラーなく実行でき、かつマッチングに**失敗**した場合はOk(false)を返す。
+///
+/// 入力された正規表現にエラーがあったり、内部的な実装エラーがある場合はErrを返す。
+pub fn do_matching_bytes(expr: &str, line: &[u8], is_depth: bool) -> Result<bool, DynError> {
+    let ast = parser::parse(expr, parser::Flags::default())?;
+    let code = codegen::get_byte_code(&ast, codegen::DEFAULT_SIZE_LIMIT)?;
+    let engine = if is_depth {
+        Engine::DepthFirst
+    } else {
+        Engine::WidthFirst
+    };
+    Ok(evaluator::eval(&code, line, engine)?)
 }
@@ -0,0 +1,298 @@
+//! Character classes represented as sorted sets of char ranges.
+use super::unicode_tables;
+use std::fmt::{self, Display};
+
+/// A set of characters, stored as sorted, non-overlapping and non-adjacent inclusive ranges.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CharClass {
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    /// Creates a class from arbitrary ranges, sorting and merging them.
+    pub fn new(ranges: Vec<(char, char)>) -> Self {
+        let mut class = CharClass { ranges };
+        class.canonicalize();
+        class
+    }
+
+    /// Creates the class of `\d`: `[0-9]` if ascii is true, otherwise Unicode decimal numbers.
+    pub fn digit(ascii: bool) -> Self {
+        if ascii {
+            CharClass::new(vec![('0', '9')])
+        } else {
+            CharClass::new(unicode_tables::DECIMAL_NUMBER.to_vec())
+        }
+    }
+
+    /// Creates the class of `\w`: `[0-9A-Za-z_]` if ascii is true, otherwise Unicode word characters.
+    pub fn word(ascii: bool) -> Self {
+        if ascii {
+            CharClass::new(vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')])
+        } else {
+            CharClass::new(unicode_tables::WORD.to_vec())
+        }
+    }
+
+    /// Creates the class of `\s`: `[\t\n\x0B\x0C\r ]` if ascii is true, otherwise Unicode white space.
+    pub fn space(ascii: bool) -> Self {
+        if ascii {
+            CharClass::new(vec![('\t', '\r'), (' ', ' ')])
+        } else {
+            CharClass::new(unicode_tables::WHITE_SPACE.to_vec())
+        }
+    }
+
+    /// Creates the class of a Unicode property such as `Greek`, `Lu` or `Script=Han`.
+    ///
+    /// The property is a General_Category or Script value, optionally prefixed by
+    /// `gc=`, `General_Category=`, `sc=` or `Script=`.
+    /// Names are matched loosely, ignoring case, spaces, `_` and `-`.
+    /// Returns None if the property is unknown.
+    #[cfg(feature = "unicode-tables")]
+    pub fn property(name: &str) -> Option<Self> {
+        use unicode_tables::{GENERAL_CATEGORY, SCRIPT};
+
+        fn normalize(name: &str) -> String {
+            name.chars()
+                .filter(|c| !matches!(c, ' ' | '_' | '-'))
+                .flat_map(char::to_lowercase)
+                .collect()
+        }
+
+        let (key, value) = match name.split_once(['=', ':']) {
+            Some((key, value)) => (Some(normalize(key)), normalize(value)),
+            None => (None, normalize(name)),
+        };
+        let tables = match key.as_deref() {
+            None => [GENERAL_CATEGORY, SCRIPT],
+            Some("gc" | "generalcategory") => [GENERAL_CATEGORY, &[]],
+            Some("sc" | "script") => [SCRIPT, &[]],
+            Some(_) => return None,
+        };
+        tables
+            .iter()
+            .flat_map(|table| table.iter())
+            .find(|(aliases, _)| aliases.iter().any(|alias| normalize(alias) == value))
+            .map(|(_, ranges)| CharClass::new(ranges.to_vec()))
+    }
+
+    /// Always returns None because the Unicode property tables are not compiled in.
+    #[cfg(not(feature = "unicode-tables"))]
+    pub fn property(_name: &str) -> Option<Self> {
+        None
+    }
+
+    /// Returns the sorted, non-overlapping ranges of the class.
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    /// Returns true if the class contains c.
+    pub fn contains(&self, c: char) -> bool {
+        ranges_contain(&self.ranges, c)
+    }
+
+    /// Replaces the class with its complement over all Unicode scalar values.
+    pub fn negate(&mut self) {
+        let mut ranges = Vec::new();
+        let mut next = Some('\0');
+        for &(start, end) in &self.ranges {
+            if let Some(n) = next
+                && n < start
+            {
+                // start > n >= '\0', so start has a predecessor.
+                ranges.push((n, prev_char(start).unwrap()));
+            }
+            next = next_char(end);
+        }
+        if let Some(n) = next {
+            ranges.push((n, char::MAX));
+        }
+        self.ranges = ranges;
+    }
+
+    /// Adds all characters of other to the class.
+    pub fn union(&mut self, other: &CharClass) {
+        self.ranges.extend_from_slice(&other.ranges);
+        self.canonicalize();
+    }
+
+    /// Adds the characters equivalent to those of the class under simple case folding.
+    ///
+    /// If ascii is true, only ASCII letters are folded.
+    pub fn case_fold(&mut self, ascii: bool) {
+        let mut folded = Vec::new();
+        for &(c, others) in unicode_tables::CASE_FOLDING_SIMPLE {
+            if (ascii && !c.is_ascii()) || !self.contains(c) {
+                continue;
+            }
+            for &other in others.iter().filter(|other| !ascii || other.is_ascii()) {
+                folded.push((other, other));
+            }
+        }
+        self.ranges.extend(folded);
+        self.canonicalize();
+    }
+
+    /// Splits the class into sequences of byte ranges matching its UTF-8 encodings.
+    ///
+    /// Each sequence matches the encodings of one contiguous range of scalar values whose
+    /// encodings have the same length and differ only within aligned byte ranges.
+    /// The sequences are disjoint, and are returned in order of their first byte.
+    pub fn utf8_sequences(&self) -> Vec<Vec<(u8, u8)>> {
+        let mut sequences = Vec::new();
+        let mut stack: Vec<(u32, u32)> = self
+            .ranges
+            .iter()
+            .rev()
+            .map(|&(start, end)| (start as u32, end as u32))
+            .collect();
+
+        'outer: while let Some((start, end)) = stack.pop() {
+            // A range such as '\u{D7FF}'..='\u{E000}' spans the surrogates.
+            if start < 0xD800 && end > 0xDFFF {
+                stack.push((0xE000, end));
+                stack.push((start, 0xD7FF));
+                continue;
+            }
+            // Split at the largest scalar values encoded in 1, 2 and 3 bytes.
+            for max in [0x7F, 0x7FF, 0xFFFF] {
+                if start <= max && max < end {
+                    stack.push((max + 1, end));
+                    stack.push((start, max));
+                    continue 'outer;
+                }
+            }
+            if end <= 0x7F {
+                sequences.push(vec![(start as u8, end as u8)]);
+                continue;
+            }
+            // Split until all but one byte of start and end cover whole continuation ranges.
+            for i in 1..4 {
+                let mask = (1 << (6 * i)) - 1;
+                if start & !mask != end & !mask {
+                    if start & mask != 0 {
+                        stack.push(((start | mask) + 1, end));
+                        stack.push((start, start | mask));
+                        continue 'outer;
+                    }
+                    if end & mask != mask {
+                        stack.push((end & !mask, end));
+                        stack.push((start, (end & !mask) - 1));
+                        continue 'outer;
+                    }
+                }
+            }
+
+            // Both bounds are scalar values because the surrogates were split off above.
+            let (mut first, mut last) = ([0; 4], [0; 4]);
+            let first = char::from_u32(start)
+                .unwrap()
+                .encode_utf8(&mut first)
+                .as_bytes();
+            let last = char::from_u32(end)
+                .unwrap()
+                .encode_utf8(&mut last)
+                .as_bytes();
+            sequences.push(first.iter().zip(last).map(|(&lo, &hi)| (lo, hi)).collect());
+        }
+
+        sequences
+    }
+
+    /// Sorts the ranges and merges overlapping or adjacent ones.
+    fn canonicalize(&mut self) {
+        self.ranges.sort_unstable();
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(self.ranges.len());
+        for &(start, end) in &self.ranges {
+            if let Some(last) = merged.last_mut()
+                && next_char(last.1).is_none_or(|n| start <= n)
+            {
+                last.1 = last.1.max(end);
+                continue;
+            }
+            merged.push((start, end));
+        }
+        self.ranges = merged;
+    }
+}
+
+/// Returns true if c is a word character.
+///
+/// If ascii is true, only `[0-9A-Za-z_]` are word characters.
+/// Otherwise, the characters of [`unicode_tables::WORD`], which include combining marks, are word characters.
+pub fn is_word_char(c: char, ascii: bool) -> bool {
+    if ascii {
+        c.is_ascii_alphanumeric() || c == '_'
+    } else {
+        ranges_contain(unicode_tables::WORD, c)
+    }
+}
+
+/// Returns true if c is in the sorted, non-overlapping ranges.
+fn ranges_contain(ranges: &[(char, char)], c: char) -> bool {
+    ranges
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                std::cmp::Ordering::Less
+            } else if start > c {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// Returns true if a and b are equal under simple case folding.
+///
+/// If ascii is true, only ASCII letters are compared case-insensitively.
+pub fn eq_ignore_case(a: char, b: char, ascii: bool) -> bool {
+    if a == b {
+        return true;
+    }
+    if ascii {
+        return a.eq_ignore_ascii_case(&b);
+    }
+    unicode_tables::CASE_FOLDING_SIMPLE
+        .binary_search_by_key(&a, |&(c, _)| c)
+        .is_ok_and(|i| unicode_tables::CASE_FOLDING_SIMPLE[i].1.contains(&b))
+}
+
+/// Returns the scalar value following c, skipping surrogates.
+fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+/// Returns the scalar value preceding c, skipping surrogates.
+fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        _ => char::from_u32((c as u32).checked_sub(1)?),
+    }
+}
+
+impl Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_char(f: &mut fmt::Formatter<'_>, c: char) -> fmt::Result {
+            match c {
+                '\\' | '[' | ']' | '-' | '^' => write!(f, "\\{}", c),
+                _ => write!(f, "{}", c.escape_debug()),
+            }
+        }
+
+        write!(f, "[")?;
+        for &(start, end) in &self.ranges {
+            write_char(f, start)?;
+            if start != end {
+                write!(f, "-")?;
+                write_char(f, end)?;
+            }
+        }
+        write!(f, "]")
+    }
+}
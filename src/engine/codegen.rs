@@ -1,4 +1,8 @@
-use super::{Instruction, parser::AST};
+use super::{
+    Instruction,
+    class::CharClass,
+    parser::{AST, Assertion, Look},
+};
 use crate::helper::safe_add;
 use std::{
     error::Error,
@@ -10,36 +14,93 @@ use std::{
 pub enum CodeGenError {
     PCOverFlow,
     FailStar,
+    FailPlus,
     FailOr,
     FailQuestion,
+    FailRepeat,
+    FailLook,
+    FailAtomic,
+    FailUtf8,
+    SizeLimitExceeded(usize),
 }
 
 impl Display for CodeGenError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "CodeGenError: {:?}", self)
+        match self {
+            CodeGenError::SizeLimitExceeded(limit) => {
+                write!(f, "CodeGenError: size limit exceeded: limit = {}", limit)
+            }
+            _ => write!(f, "CodeGenError: {:?}", self),
+        }
     }
 }
 
 impl Error for CodeGenError {}
 
+/// 生成する命令数の上限のデフォルト値。
+pub const DEFAULT_SIZE_LIMIT: usize = 1 << 20;
+
 /// Code generator.
 #[derive(Default, Debug)]
 struct Generator {
     pc: usize,
     insts: Vec<Instruction>,
+    mark: usize,       // 次に割り当てる、キャプチャグループ以外の位置を保存するスロット
+    size_limit: usize, // 生成する命令数の上限
+    bytes: bool,       // バイト単位の入力にマッチするコードを生成する
 }
 
 /// コード生成を行う関数。
-pub fn get_code(ast: &AST) -> Result<Vec<Instruction>, CodeGenError> {
-    let mut generator = Generator::default();
-    generator.gen_code(ast)?;
+///
+/// 生成する命令数がsize_limitを超える場合はエラーを返す。
+pub fn get_code(ast: &AST, size_limit: usize) -> Result<Vec<Instruction>, CodeGenError> {
+    let mut generator = Generator::new(ast, size_limit);
+    generator.gen_code(ast, false)?;
+    Ok(generator.insts)
+}
+
+/// バイト単位の入力とマッチングを行うコードを生成する関数。
+///
+/// 文字と文字クラスは、そのUTF-8のバイト列にマッチするbyte命令とbyte_range命令の並びとなる。
+/// 生成する命令数がsize_limitを超える場合はエラーを返す。
+pub fn get_byte_code(ast: &AST, size_limit: usize) -> Result<Vec<Instruction>, CodeGenError> {
+    let mut generator = Generator::new(ast, size_limit);
+    generator.bytes = true;
+    generator.gen_code(ast, false)?;
+    Ok(generator.insts)
+}
+
+/// 入力全体とのマッチングを行うコードを生成する関数。
+///
+/// マッチの直前に入力の末尾であることを確認する命令を生成する。
+/// 先頭からマッチングを行えば、入力全体にマッチするかを判定できる。
+pub fn get_full_code(ast: &AST, size_limit: usize) -> Result<Vec<Instruction>, CodeGenError> {
+    let mut generator = Generator::new(ast, size_limit);
+    generator.gen_code(ast, true)?;
     Ok(generator.insts)
 }
 
 impl Generator {
+    fn new(ast: &AST, size_limit: usize) -> Self {
+        Generator {
+            // キャプチャグループのスロットの後ろを、繰り返しの開始位置の保存に使う
+            mark: (ast.max_capture() + 1) * 2,
+            size_limit,
+            ..Default::default()
+        }
+    }
+
     /// コード生成を行う関数の入り口
-    fn gen_code(&mut self, ast: &AST) -> Result<(), CodeGenError> {
+    ///
+    /// マッチ全体の範囲は0番目のキャプチャグループとして保存する。
+    /// fullがtrueの場合は、マッチの直前に入力の末尾であることを確認する。
+    fn gen_code(&mut self, ast: &AST, full: bool) -> Result<(), CodeGenError> {
+        self.gen_save(0)?;
         self.gen_expr(ast)?;
+        if full {
+            self.gen_assert(Assertion::EndText)?;
+        }
+        self.gen_save(1)?;
         self.inc_pc()?;
         self.insts.push(Instruction::Match);
         Ok(())
@@ -50,11 +111,27 @@ impl Generator {
         match ast {
             AST::Char(c) => self.gen_char(*c)?,
             AST::Or(e1, e2) => self.gen_or(e1, e2)?,
-            AST::Dot => self.gen_dot()?,
-            AST::Plus(e) => self.gen_plus(e)?,
-            AST::Star(e) => self.gen_star(e)?,
-            AST::Question(e) => self.gen_question(e)?,
+            AST::Dot(matches_new_line) => self.gen_dot(*matches_new_line)?,
+            AST::Class(class) => self.gen_class(class)?,
+            AST::Assert(assertion) => self.gen_assert(*assertion)?,
+            AST::Plus(e, greedy) => self.gen_plus(e, *greedy)?,
+            AST::Star(e, greedy) => self.gen_star(e, *greedy)?,
+            AST::Question(e, greedy) => self.gen_question(e, *greedy)?,
+            AST::Repeat {
+                ast: e,
+                min,
+                max,
+                greedy,
+            } => self.gen_repeat(e, *min, *max, *greedy)?,
             AST::Seq(v) => self.gen_seq(v)?,
+            AST::Capture(n, _, e) => self.gen_capture(*n, e)?,
+            AST::Backref {
+                group,
+                case_insensitive,
+                ascii,
+            } => self.gen_backref(*group, *case_insensitive, *ascii)?,
+            AST::Look(look, e) => self.gen_look(*look, e)?,
+            AST::Atomic(e) => self.gen_atomic(e)?,
         }
 
         Ok(())
@@ -62,19 +139,232 @@ impl Generator {
 
     /// char命令生成関数
     fn gen_char(&mut self, c: char) -> Result<(), CodeGenError> {
+        if self.bytes {
+            let mut buf = [0; 4];
+            for &b in c.encode_utf8(&mut buf).as_bytes() {
+                self.insts.push(Instruction::Byte(b));
+                self.inc_pc()?;
+            }
+            return Ok(());
+        }
+
         let inst = Instruction::Char(c);
         self.insts.push(inst);
         self.inc_pc()?;
         Ok(())
     }
 
-    fn gen_dot(&mut self) -> Result<(), CodeGenError> {
-        let inst = Instruction::Any;
+    /// any命令生成関数
+    ///
+    /// matches_new_lineがfalseの場合は、改行にマッチしないany_not_newline命令を生成する。
+    fn gen_dot(&mut self, matches_new_line: bool) -> Result<(), CodeGenError> {
+        if self.bytes {
+            let class = if matches_new_line {
+                CharClass::new(vec![('\0', char::MAX)])
+            } else {
+                let mut class = CharClass::new(vec![('\n', '\n')]);
+                class.negate();
+                class
+            };
+            return self.gen_utf8(&class);
+        }
+
+        let inst = if matches_new_line {
+            Instruction::Any
+        } else {
+            Instruction::AnyNotNewline
+        };
+        self.insts.push(inst);
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    /// class命令生成関数
+    fn gen_class(&mut self, class: &CharClass) -> Result<(), CodeGenError> {
+        if self.bytes {
+            return self.gen_utf8(class);
+        }
+
+        let inst = Instruction::Class(class.clone());
+        self.insts.push(inst);
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    /// 文字クラスのUTF-8のバイト列にマッチするコード生成
+    ///
+    /// 文字クラスをバイトの範囲の並びに分割し、いずれかの並びにマッチするコードを生成する。
+    /// 並びの先頭バイトは互いに重ならないため、分岐の優先度はマッチに影響しない。
+    ///
+    /// ```text
+    ///     split L1, L2
+    /// L1: 1番目の並びのbyte_range命令
+    ///     jump L3
+    /// L2: 2番目の並びのbyte_range命令
+    /// L3:
+    /// ```
+    ///
+    /// 空の文字クラスは、どのバイトにもマッチしないclass命令となる。
+    fn gen_utf8(&mut self, class: &CharClass) -> Result<(), CodeGenError> {
+        let sequences = class.utf8_sequences();
+        let Some((last, rest)) = sequences.split_last() else {
+            self.insts.push(Instruction::Class(class.clone()));
+            return self.inc_pc();
+        };
+
+        let mut jump_addrs = Vec::new();
+        for sequence in rest {
+            // split Ln, Ln+1
+            let split_addr = self.pc;
+            self.inc_pc()?;
+            self.insts.push(Instruction::Split(self.pc, 0));
+
+            // Ln: 並びのコード
+            self.gen_byte_ranges(sequence)?;
+
+            // jump L3
+            jump_addrs.push(self.pc);
+            self.insts.push(Instruction::Jump(0)); // L3は仮に0
+            self.inc_pc()?;
+
+            // Ln+1の値を設定
+            if let Some(Instruction::Split(_, l2)) = self.insts.get_mut(split_addr) {
+                *l2 = self.pc;
+            } else {
+                return Err(CodeGenError::FailUtf8);
+            }
+        }
+        self.gen_byte_ranges(last)?;
+
+        // L3の値を設定
+        for addr in jump_addrs {
+            if let Some(Instruction::Jump(l3)) = self.insts.get_mut(addr) {
+                *l3 = self.pc;
+            } else {
+                return Err(CodeGenError::FailUtf8);
+            }
+        }
+        Ok(())
+    }
+
+    /// バイトの範囲の並びにマッチする、byte命令とbyte_range命令の生成関数
+    fn gen_byte_ranges(&mut self, ranges: &[(u8, u8)]) -> Result<(), CodeGenError> {
+        for &(lo, hi) in ranges {
+            let inst = if lo == hi {
+                Instruction::Byte(lo)
+            } else {
+                Instruction::ByteRange(lo, hi)
+            };
+            self.insts.push(inst);
+            self.inc_pc()?;
+        }
+        Ok(())
+    }
+
+    /// アサーション命令生成関数
+    fn gen_assert(&mut self, assertion: Assertion) -> Result<(), CodeGenError> {
+        let inst = match assertion {
+            Assertion::StartText => Instruction::AssertStart,
+            Assertion::EndText => Instruction::AssertEnd,
+            Assertion::StartLine => Instruction::AssertStartLine,
+            Assertion::EndLine => Instruction::AssertEndLine,
+            Assertion::WordBoundary { ascii } => Instruction::WordBoundary { ascii },
+            Assertion::NotWordBoundary { ascii } => Instruction::NotWordBoundary { ascii },
+        };
+        self.insts.push(inst);
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    /// backref命令生成関数
+    fn gen_backref(
+        &mut self,
+        group: usize,
+        case_insensitive: bool,
+        ascii: bool,
+    ) -> Result<(), CodeGenError> {
+        let inst = Instruction::Backref {
+            group,
+            case_insensitive,
+            ascii,
+        };
         self.insts.push(inst);
         self.inc_pc()?;
         Ok(())
     }
 
+    /// 先読み・後読みのコード生成
+    ///
+    /// ```text
+    ///     look L1
+    ///     eのコード
+    ///     match
+    /// L1:
+    /// ```
+    ///
+    /// look命令の次からmatch命令までが、先読み・後読みで評価する部分プログラムとなる。
+    /// バイト単位の入力では、1文字は1から4バイトとなるため、後読みの長さの上限を4倍にする。
+    fn gen_look(&mut self, look: Look, e: &AST) -> Result<(), CodeGenError> {
+        let look = match look {
+            Look::Behind { negated, min, max } if self.bytes => Look::Behind {
+                negated,
+                min,
+                max: max.checked_mul(4).ok_or(CodeGenError::FailLook)?,
+            },
+            _ => look,
+        };
+        let look_addr = self.pc;
+        self.insts.push(Instruction::Look { look, next: 0 }); // L1は仮に0
+        self.inc_pc()?;
+
+        self.gen_expr(e)?;
+        self.insts.push(Instruction::Match);
+        self.inc_pc()?;
+
+        // L1の値を設定
+        if let Some(Instruction::Look { next, .. }) = self.insts.get_mut(look_addr) {
+            *next = self.pc;
+            Ok(())
+        } else {
+            Err(CodeGenError::FailLook)
+        }
+    }
+
+    /// アトミックグループのコード生成
+    ///
+    /// ```text
+    ///     atomic L1, mark
+    ///     eのコード
+    ///     save mark
+    ///     cut
+    /// L1:
+    /// ```
+    ///
+    /// atomic命令の次からcut命令までが部分プログラムとなり、最初に見つかったマッチの
+    /// 終了位置をmarkに保存する。残りの分岐は破棄され、markの位置からL1へ進む。
+    fn gen_atomic(&mut self, e: &AST) -> Result<(), CodeGenError> {
+        let mark = self.new_mark()?;
+        let atomic_addr = self.pc;
+        self.insts.push(Instruction::Atomic {
+            slot: mark,
+            next: 0,
+        }); // L1は仮に0
+        self.inc_pc()?;
+
+        self.gen_expr(e)?;
+        self.gen_save(mark)?;
+        self.insts.push(Instruction::Cut);
+        self.inc_pc()?;
+
+        // L1の値を設定
+        if let Some(Instruction::Atomic { next, .. }) = self.insts.get_mut(atomic_addr) {
+            *next = self.pc;
+            Ok(())
+        } else {
+            Err(CodeGenError::FailAtomic)
+        }
+    }
+
     fn gen_or(&mut self, e1: &AST, e2: &AST) -> Result<(), CodeGenError> {
         let split_addr = self.pc;
         self.inc_pc()?; // L1はsplit命令の次のアドレスのため、increment
@@ -104,24 +394,77 @@ impl Generator {
             *l3 = self.pc;
             Ok(())
         } else {
-            return Err(CodeGenError::FailOr);
+            Err(CodeGenError::FailOr)
         }
     }
 
-    fn gen_plus(&mut self, e: &AST) -> Result<(), CodeGenError> {
+    /// +のコード生成
+    ///
+    /// greedyがfalseの場合は、分岐の優先度を入れ替えたsplit L2, L1を生成する。
+    fn gen_plus(&mut self, e: &AST, greedy: bool) -> Result<(), CodeGenError> {
+        if e.is_nullable() {
+            return self.gen_plus_nullable(e, greedy);
+        }
+
         // L1: eのコード
         let l1 = self.pc;
         self.gen_expr(e)?;
 
         // split L1, L2
         self.inc_pc()?;
-        let split = Instruction::Split(l1, self.pc);
+        let split = if greedy {
+            Instruction::Split(l1, self.pc)
+        } else {
+            Instruction::Split(self.pc, l1)
+        };
         self.insts.push(split);
 
         Ok(())
     }
 
-    fn gen_star(&mut self, e: &AST) -> Result<(), CodeGenError> {
+    /// 空文字列にマッチし得るeに対する+のコード生成
+    ///
+    /// 入力を消費しなかった繰り返しを打ち切り、無限ループを防ぐ。
+    ///
+    /// ```text
+    /// L1: save mark
+    ///     eのコード
+    ///     split L2, L3
+    /// L2: progress mark
+    ///     jump L1
+    /// L3:
+    /// ```
+    fn gen_plus_nullable(&mut self, e: &AST, greedy: bool) -> Result<(), CodeGenError> {
+        let mark = self.new_mark()?;
+
+        // L1: save mark
+        let l1 = self.pc;
+        self.gen_save(mark)?;
+
+        // eのコード
+        self.gen_expr(e)?;
+
+        // split L2, L3
+        let split_addr = self.pc;
+        self.inc_pc()?;
+        self.insts.push(Instruction::Split(self.pc, 0));
+
+        // L2: progress mark
+        self.inc_pc()?;
+        self.insts.push(Instruction::Progress(mark));
+
+        // jump L1
+        self.inc_pc()?;
+        self.insts.push(Instruction::Jump(l1));
+
+        // L3の値を設定
+        self.set_split_exit(split_addr, greedy, CodeGenError::FailPlus)
+    }
+
+    /// *のコード生成
+    ///
+    /// greedyがfalseの場合は、分岐の優先度を入れ替えたsplit L3, L2を生成する。
+    fn gen_star(&mut self, e: &AST, greedy: bool) -> Result<(), CodeGenError> {
         // L1: split L2, L3
         let l1 = self.pc;
         self.inc_pc()?;
@@ -129,22 +472,32 @@ impl Generator {
         self.insts.push(split);
 
         // L2: eのコード
+        // eが空文字列にマッチし得る場合は、入力を消費しなかった繰り返しを打ち切る
+        let mark = if e.is_nullable() {
+            let mark = self.new_mark()?;
+            self.gen_save(mark)?;
+            Some(mark)
+        } else {
+            None
+        };
         self.gen_expr(e)?;
+        if let Some(mark) = mark {
+            self.inc_pc()?;
+            self.insts.push(Instruction::Progress(mark));
+        }
 
         // jump L1
         self.inc_pc()?;
         self.insts.push(Instruction::Jump(l1));
 
         // L3の値を設定
-        if let Some(Instruction::Split(_, l3)) = self.insts.get_mut(l1) {
-            *l3 = self.pc;
-            Ok(())
-        } else {
-            Err(CodeGenError::FailStar)
-        }
+        self.set_split_exit(l1, greedy, CodeGenError::FailStar)
     }
 
-    fn gen_question(&mut self, e: &AST) -> Result<(), CodeGenError> {
+    /// ?のコード生成
+    ///
+    /// greedyがfalseの場合は、分岐の優先度を入れ替えたsplit L2, L1を生成する。
+    fn gen_question(&mut self, e: &AST, greedy: bool) -> Result<(), CodeGenError> {
         // split L1, L2
         let split_addr = self.pc;
         self.inc_pc()?;
@@ -155,14 +508,103 @@ impl Generator {
         self.gen_expr(e)?;
 
         // L2の値を設定
-        if let Some(Instruction::Split(_, l2)) = self.insts.get_mut(split_addr) {
+        self.set_split_exit(split_addr, greedy, CodeGenError::FailQuestion)
+    }
+
+    /// addrにあるsplit命令の2番目の分岐先を、現在のpcに設定する。
+    ///
+    /// greedyがfalseの場合は2つの分岐先を入れ替え、繰り返しを抜ける分岐を優先する。
+    fn set_split_exit(
+        &mut self,
+        addr: usize,
+        greedy: bool,
+        err: CodeGenError,
+    ) -> Result<(), CodeGenError> {
+        if let Some(Instruction::Split(l1, l2)) = self.insts.get_mut(addr) {
             *l2 = self.pc;
+            if !greedy {
+                std::mem::swap(l1, l2);
+            }
             Ok(())
         } else {
-            Err(CodeGenError::FailQuestion)
+            Err(err)
         }
     }
 
+    /// n番目のキャプチャグループのコード生成
+    ///
+    /// ```text
+    ///     save 2n
+    ///     eのコード
+    ///     save 2n+1
+    /// ```
+    fn gen_capture(&mut self, n: usize, e: &AST) -> Result<(), CodeGenError> {
+        self.gen_save(n * 2)?;
+        self.gen_expr(e)?;
+        self.gen_save(n * 2 + 1)
+    }
+
+    /// save命令生成関数
+    fn gen_save(&mut self, slot: usize) -> Result<(), CodeGenError> {
+        self.insts.push(Instruction::Save(slot));
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    /// 回数を指定した繰り返しのコード生成
+    ///
+    /// eをmin回展開した後、上限がない場合はe*を、
+    /// 上限がある場合は残りの回数だけ入れ子になったe?を生成する。
+    /// e{2,4}の場合は以下のようになる。
+    ///
+    /// ```text
+    ///     eのコード
+    ///     eのコード
+    ///     split L1, L3
+    /// L1: eのコード
+    ///     split L2, L3
+    /// L2: eのコード
+    /// L3:
+    /// ```
+    fn gen_repeat(
+        &mut self,
+        e: &AST,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    ) -> Result<(), CodeGenError> {
+        for _ in 0..min {
+            // 命令を生成しない式は、何度展開しても同じ
+            let pc = self.pc;
+            self.gen_expr(e)?;
+            if self.pc == pc {
+                break;
+            }
+        }
+
+        let Some(max) = max else {
+            return self.gen_star(e, greedy);
+        };
+
+        let mut split_addrs = Vec::new();
+        for _ in min..max {
+            // split Ln, L3
+            split_addrs.push(self.pc);
+            self.inc_pc()?;
+            self.insts.push(Instruction::Split(self.pc, 0));
+
+            // Ln: eのコード
+            self.gen_expr(e)?;
+        }
+
+        // L3の値を設定
+        for addr in split_addrs {
+            self.set_split_exit(addr, greedy, CodeGenError::FailRepeat)?;
+        }
+
+        Ok(())
+    }
+
     // Sequence内に入っているそれぞれの文字をgen_expr()に入れてmatch文で分岐させる。
     fn gen_seq(&mut self, exprs: &[AST]) -> Result<(), CodeGenError> {
         for e in exprs {
@@ -172,8 +614,22 @@ impl Generator {
         Ok(())
     }
 
+    /// 繰り返しの開始位置など、キャプチャグループ以外の位置を保存するスロットを割り当てる。
+    fn new_mark(&mut self) -> Result<usize, CodeGenError> {
+        let mark = self.mark;
+        safe_add(&mut self.mark, &1, || CodeGenError::PCOverFlow)?;
+        Ok(mark)
+    }
+
     /// Increment program counter.
+    ///
+    /// 命令数が上限を超える場合はエラーを返す。
     fn inc_pc(&mut self) -> Result<(), CodeGenError> {
-        safe_add(&mut self.pc, &1, || CodeGenError::PCOverFlow)
+        safe_add(&mut self.pc, &1, || CodeGenError::PCOverFlow)?;
+        if self.pc > self.size_limit {
+            Err(CodeGenError::SizeLimitExceeded(self.size_limit))
+        } else {
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,297 @@
+//! 命令列から事前に構築し、最小化したDFA。
+use super::{
+    Instruction,
+    class::CharClass,
+    lazy_dfa::{self, EDGE, NEWLINE, StateKey, WORD_ASCII, WORD_UNICODE},
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display},
+};
+
+/// Type representing a DFA construction error.
+#[derive(Debug)]
+pub enum DfaError {
+    StateLimitExceeded(usize),
+}
+
+impl Display for DfaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DfaError::StateLimitExceeded(limit) => {
+                write!(f, "DfaError: state limit exceeded: limit = {}", limit)
+            }
+        }
+    }
+}
+
+impl Error for DfaError {}
+
+/// 最小化前の状態数の上限のデフォルト値。
+pub const DEFAULT_STATE_LIMIT: usize = 10_000;
+
+/// 部分集合構成法で構築し、Hopcroftのアルゴリズムで最小化したDFA。
+///
+/// 文字は、命令列の中で区別されない文字ごとに文字クラスへまとめられ、
+/// 状態と文字クラスから遷移先を引く表を保持する。
+/// マッチングは1文字あたり表を1回引くだけで行い、バックトラックやスレッドのリストを必要としない。
+#[derive(Debug)]
+pub struct Dfa {
+    boundaries: Vec<u32>,       // 各文字クラスの先頭のコードポイント（昇順）
+    table: Vec<usize>,          // 状態 * 文字クラス数 + 文字クラスで引く遷移先
+    eoi: Vec<bool>,             // 入力の末尾でマッチするか
+    settled: Vec<Option<bool>>, // 以降の入力によらず結果が決まる状態の結果
+}
+
+impl Dfa {
+    /// 命令列から、いずれかの位置から始まるマッチを判定するDFAを構築する。
+    ///
+    /// 後方参照、先読み・後読み、アトミックグループを含む命令列は決定化できないため、Ok(None)を返す。
+    /// 最小化前の状態数がstate_limitを超える場合はエラーを返す。
+    pub fn new(inst: &[Instruction], state_limit: usize) -> Result<Option<Dfa>, DfaError> {
+        if !lazy_dfa::is_supported(inst) {
+            return Ok(None);
+        }
+
+        let (boundaries, mask) = alphabet(inst);
+        let classes = boundaries.len();
+        let (table, eoi) = determinize(inst, &boundaries, mask, state_limit)?;
+        let (table, eoi) = minimize(&table, &eoi, classes, START);
+
+        // 自身にのみ遷移する状態では、以降の入力によらず結果が決まる
+        let settled = (0..eoi.len())
+            .map(|state| {
+                let row = &table[state * classes..(state + 1) * classes];
+                row.iter().all(|&next| next == state).then_some(eoi[state])
+            })
+            .collect();
+
+        Ok(Some(Dfa {
+            boundaries,
+            table,
+            eoi,
+            settled,
+        }))
+    }
+
+    /// lineのいずれかの位置から始まるマッチがあるか判定する。
+    pub fn is_match(&self, line: &[char]) -> bool {
+        let classes = self.boundaries.len();
+        let mut state = 0;
+        for &c in line {
+            if let Some(matched) = self.settled[state] {
+                return matched;
+            }
+            let class = self.boundaries.partition_point(|&b| b <= c as u32) - 1;
+            state = self.table[state * classes + class];
+        }
+        self.eoi[state]
+    }
+}
+
+// 部分集合構成法で生成する状態のうち、特別な状態の番号
+const DEAD: usize = 0; // マッチし得ない状態
+const MATCH: usize = 1; // マッチした状態
+const START: usize = 2; // 初期状態
+
+/// 命令列の中で区別されない文字をまとめた文字クラスの、先頭のコードポイントを返す。
+///
+/// 合わせて、アサーション命令の評価に必要な、直前の文字の種類のフラグを返す。
+fn alphabet(inst: &[Instruction]) -> (Vec<u32>, u8) {
+    let mut mask = 0;
+    let mut ranges = vec![('\n', '\n')];
+    for i in inst {
+        match i {
+            Instruction::Char(c) => ranges.push((*c, *c)),
+            Instruction::Class(class) => ranges.extend_from_slice(class.ranges()),
+            Instruction::AssertStart => mask |= EDGE,
+            Instruction::AssertStartLine => mask |= EDGE | NEWLINE,
+            Instruction::WordBoundary { ascii } | Instruction::NotWordBoundary { ascii } => {
+                mask |= if *ascii { WORD_ASCII } else { WORD_UNICODE };
+                ranges.extend_from_slice(CharClass::word(*ascii).ranges());
+            }
+            _ => (),
+        }
+    }
+
+    // サロゲートの範囲は文字として現れないため、境界は直後の文字に移す
+    let mut boundaries: Vec<u32> = ranges
+        .iter()
+        .flat_map(|&(start, end)| [start as u32, end as u32 + 1])
+        .chain([0])
+        .map(|b| {
+            if (0xD800..0xE000).contains(&b) {
+                0xE000
+            } else {
+                b
+            }
+        })
+        .filter(|&b| b <= char::MAX as u32)
+        .collect();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+    (boundaries, mask)
+}
+
+/// 部分集合構成法で、命令列から遷移表と入力の末尾でマッチするかの表を構築する。
+///
+/// 状態の直前の文字の種類はmaskのフラグのみを区別する。
+fn determinize(
+    inst: &[Instruction],
+    boundaries: &[u32],
+    mask: u8,
+    state_limit: usize,
+) -> Result<(Vec<usize>, Vec<bool>), DfaError> {
+    let start = StateKey {
+        pcs: vec![0],
+        prev: EDGE & mask,
+        anchored: false,
+    };
+    let mut keys = vec![start.clone()]; // START以降の状態
+    let mut index = HashMap::from([(start, START)]);
+
+    // マッチし得ない状態とマッチした状態は、自身にのみ遷移する
+    let mut table = [DEAD, MATCH]
+        .iter()
+        .flat_map(|&state| std::iter::repeat_n(state, boundaries.len()))
+        .collect::<Vec<_>>();
+    let mut eoi = vec![false, true];
+
+    let mut i = 0;
+    while i < keys.len() {
+        let key = keys[i].clone();
+        eoi.push(lazy_dfa::step(inst, &key, None).1);
+
+        for &b in boundaries {
+            // 境界はサロゲートを含まないため、必ず文字に変換できる
+            let c = char::from_u32(b).unwrap();
+            let (next, matched) = lazy_dfa::step(inst, &key, Some(c));
+            let next = match next {
+                _ if matched => MATCH,
+                None => DEAD,
+                Some(mut next) => {
+                    next.prev &= mask;
+                    if next.pcs.is_empty() {
+                        DEAD
+                    } else if let Some(&state) = index.get(&next) {
+                        state
+                    } else {
+                        if START + keys.len() >= state_limit {
+                            return Err(DfaError::StateLimitExceeded(state_limit));
+                        }
+                        let state = START + keys.len();
+                        index.insert(next.clone(), state);
+                        keys.push(next);
+                        state
+                    }
+                }
+            };
+            table.push(next);
+        }
+        i += 1;
+    }
+
+    Ok((table, eoi))
+}
+
+/// Hopcroftのアルゴリズムで、遷移表を最小化する。
+///
+/// 到達できない状態は除き、startの状態が0番になるよう番号を振り直す。
+fn minimize(
+    table: &[usize],
+    eoi: &[bool],
+    classes: usize,
+    start: usize,
+) -> (Vec<usize>, Vec<bool>) {
+    // 遷移先と文字クラスから、遷移元を引く表
+    let mut inverse = vec![Vec::new(); eoi.len() * classes];
+    for (i, &next) in table.iter().enumerate() {
+        inverse[next * classes + i % classes].push(i / classes);
+    }
+
+    // 入力の末尾でマッチするかで初期の分割を作る
+    let mut blocks: Vec<Vec<usize>> = [true, false]
+        .iter()
+        .map(|&e| (0..eoi.len()).filter(|&s| eoi[s] == e).collect::<Vec<_>>())
+        .filter(|block| !block.is_empty())
+        .collect();
+    let mut block_of = vec![0; eoi.len()];
+    for (b, block) in blocks.iter().enumerate() {
+        for &s in block {
+            block_of[s] = b;
+        }
+    }
+
+    let mut work: Vec<usize> = (0..blocks.len()).collect();
+    let mut in_work = vec![true; blocks.len()];
+    while let Some(splitter) = work.pop() {
+        in_work[splitter] = false;
+        let splitter = blocks[splitter].clone();
+
+        for class in 0..classes {
+            // splitterに遷移する状態を、属するブロックごとにまとめる
+            let mut sources: HashMap<usize, Vec<usize>> = HashMap::new();
+            for &target in &splitter {
+                for &s in &inverse[target * classes + class] {
+                    sources.entry(block_of[s]).or_default().push(s);
+                }
+            }
+
+            for (b, members) in sources {
+                if members.len() == blocks[b].len() {
+                    continue;
+                }
+                // ブロックを、splitterに遷移する状態とそれ以外に分割する
+                let new = blocks.len();
+                for &s in &members {
+                    block_of[s] = new;
+                }
+                blocks[b].retain(|&s| block_of[s] == b);
+                blocks.push(members);
+
+                if in_work[b] {
+                    work.push(new);
+                    in_work.push(true);
+                } else {
+                    let smaller = if blocks[b].len() <= blocks[new].len() {
+                        b
+                    } else {
+                        new
+                    };
+                    in_work.push(false);
+                    in_work[smaller] = true;
+                    work.push(smaller);
+                }
+            }
+        }
+    }
+
+    // startから到達できるブロックに、到達した順に番号を振る
+    let mut id = vec![None; blocks.len()];
+    let mut order = vec![block_of[start]];
+    id[block_of[start]] = Some(0);
+    let mut i = 0;
+    while i < order.len() {
+        let s = blocks[order[i]][0];
+        for &next in &table[s * classes..(s + 1) * classes] {
+            let b = block_of[next];
+            if id[b].is_none() {
+                id[b] = Some(order.len());
+                order.push(b);
+            }
+        }
+        i += 1;
+    }
+
+    let mut min_table = Vec::with_capacity(order.len() * classes);
+    let mut min_eoi = Vec::with_capacity(order.len());
+    for &b in &order {
+        let s = blocks[b][0];
+        for &next in &table[s * classes..(s + 1) * classes] {
+            min_table.push(id[block_of[next]].unwrap());
+        }
+        min_eoi.push(eoi[s]);
+    }
+    (min_table, min_eoi)
+}
@@ -1,7 +1,11 @@
-use super::Instruction;
+use super::{
+    Instruction,
+    class::{eq_ignore_case, is_word_char},
+    parser::Look,
+};
 use crate::helper::safe_add;
 use std::{
-    collections::VecDeque,
+    collections::HashSet,
     error::Error,
     fmt::{self, Display},
 };
@@ -11,31 +15,440 @@ pub enum EvalError {
     PCOverFlow,
     SPOverFlow,
     InvalidPC,
-    InvalidContext,
+    InvalidSlot,
+    UnsupportedBackref(Engine),
+    UnsupportedAtomic(Engine),
 }
 
 impl Display for EvalError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "CodeGenError: {:?}", self)
+        match self {
+            EvalError::UnsupportedBackref(engine) => write!(
+                f,
+                "EvalError: backreferences are not supported by the {:?} engine, use DepthFirst",
+                engine
+            ),
+            EvalError::UnsupportedAtomic(engine) => write!(
+                f,
+                "EvalError: atomic groups and possessive quantifiers are not supported by the {:?} engine, use DepthFirst",
+                engine
+            ),
+            _ => write!(f, "CodeGenError: {:?}", self),
+        }
     }
 }
 
 impl Error for EvalError {}
 
-pub fn eval(inst: &[Instruction], line: &[char], is_depth: bool) -> Result<bool, EvalError> {
-    if is_depth {
-        eval_depth(inst, line, 0, 0)
+/// マッチングに利用する評価器の種類。
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// 再帰による深さ優先探索
+    #[default]
+    DepthFirst,
+    /// Pike VMによる幅優先探索
+    WidthFirst,
+    /// 訪問済みの(pc, sp)を記録する、有界なバックトラックによる深さ優先探索
+    ///
+    /// 記録に必要なビット数（命令数 × (入力長 + 1)）が[`MAX_VISITED_BITS`]を超える場合は、
+    /// 代わりにWidthFirstで評価する。
+    Backtrack,
+}
+
+impl Engine {
+    /// 後方参照をサポートする評価器の場合にtrueを返す。
+    ///
+    /// 後方参照の評価にはキャプチャした位置が必要なため、(pc, sp)のみで状態を区別して
+    /// 計算量を抑えるWidthFirstとBacktrackはサポートしない。
+    pub fn supports_backref(&self) -> bool {
+        matches!(self, Engine::DepthFirst)
+    }
+
+    /// アトミックグループと絶対最大量指定子をサポートする評価器の場合にtrueを返す。
+    ///
+    /// アトミックグループの後は入力を消費した位置から評価を再開するため、
+    /// 全てのスレッドを同じ位置で進めるWidthFirstはサポートしない。
+    /// また、グループの中の(pc, sp)の成否は、グループより後の命令の成否によって変わるため、
+    /// 一度失敗した(pc, sp)を再び評価しないBacktrackもサポートしない。
+    pub fn supports_atomic(&self) -> bool {
+        matches!(self, Engine::DepthFirst)
+    }
+}
+
+/// 評価器が入力として扱う記号。
+///
+/// 文字単位の入力ではcharを、バイト単位の入力ではUTF-8のバイト列を復号せずにu8のまま扱う。
+pub trait Symbol: Copy + Eq {
+    /// 入力を消費する命令がこの記号にマッチする場合にtrueを返す。
+    fn is_accepted_by(self, inst: &Instruction) -> bool;
+
+    /// 改行の場合にtrueを返す。
+    fn is_newline(self) -> bool;
+
+    /// lineのsp番目の位置の直前の文字が、単語構成文字の場合にtrueを返す。
+    fn is_word_before(line: &[Self], sp: usize, ascii: bool) -> bool;
+
+    /// lineのsp番目の位置から始まる文字が、単語構成文字の場合にtrueを返す。
+    fn is_word_after(line: &[Self], sp: usize, ascii: bool) -> bool;
+
+    /// lineのsp番目の位置が、1文字の途中でない場合にtrueを返す。
+    fn is_char_boundary(line: &[Self], sp: usize) -> bool;
+
+    /// inputの先頭が大文字と小文字を区別せずにgroupと等しい場合は、inputの該当部分の長さを返す。
+    fn match_ignore_case(group: &[Self], input: &[Self], ascii: bool) -> Option<usize>;
+}
+
+impl Symbol for char {
+    fn is_accepted_by(self, inst: &Instruction) -> bool {
+        match inst {
+            Instruction::Char(c) => *c == self,
+            Instruction::Any => true,
+            Instruction::AnyNotNewline => self != '\n',
+            Instruction::Class(class) => class.contains(self),
+            _ => false,
+        }
+    }
+
+    fn is_newline(self) -> bool {
+        self == '\n'
+    }
+
+    fn is_word_before(line: &[Self], sp: usize, ascii: bool) -> bool {
+        sp.checked_sub(1)
+            .and_then(|i| line.get(i))
+            .is_some_and(|&c| is_word_char(c, ascii))
+    }
+
+    fn is_word_after(line: &[Self], sp: usize, ascii: bool) -> bool {
+        line.get(sp).is_some_and(|&c| is_word_char(c, ascii))
+    }
+
+    fn is_char_boundary(_line: &[Self], _sp: usize) -> bool {
+        true
+    }
+
+    fn match_ignore_case(group: &[Self], input: &[Self], ascii: bool) -> Option<usize> {
+        let input = input.get(..group.len())?;
+        group
+            .iter()
+            .zip(input)
+            .all(|(&a, &b)| eq_ignore_case(a, b, ascii))
+            .then_some(group.len())
+    }
+}
+
+impl Symbol for u8 {
+    fn is_accepted_by(self, inst: &Instruction) -> bool {
+        match inst {
+            Instruction::Byte(b) => *b == self,
+            Instruction::ByteRange(lo, hi) => (*lo..=*hi).contains(&self),
+            _ => false,
+        }
+    }
+
+    fn is_newline(self) -> bool {
+        self == b'\n'
+    }
+
+    fn is_word_before(line: &[Self], sp: usize, ascii: bool) -> bool {
+        // spで終わる、1文字分のUTF-8のバイト列を探す
+        (1..=sp.min(4))
+            .find_map(|len| decode_utf8(&line[sp - len..sp]).filter(|&(_, n)| n == len))
+            .is_some_and(|(c, _)| is_word_char(c, ascii))
+    }
+
+    fn is_word_after(line: &[Self], sp: usize, ascii: bool) -> bool {
+        line.get(sp..)
+            .and_then(decode_utf8)
+            .is_some_and(|(c, _)| is_word_char(c, ascii))
+    }
+
+    fn is_char_boundary(line: &[Self], sp: usize) -> bool {
+        // spより前から始まる1文字分のUTF-8のバイト列が、spをまたぐか調べる
+        !(1..=sp.min(3)).any(|len| {
+            line.get(sp - len..)
+                .and_then(decode_utf8)
+                .is_some_and(|(_, n)| n > len)
+        })
+    }
+
+    fn match_ignore_case(group: &[Self], input: &[Self], ascii: bool) -> Option<usize> {
+        let (mut i, mut j) = (0, 0);
+        while i < group.len() {
+            let rest = input.get(j..).filter(|rest| !rest.is_empty())?;
+            match (decode_utf8(&group[i..]), decode_utf8(rest)) {
+                (Some((a, n)), Some((b, m))) if eq_ignore_case(a, b, ascii) => {
+                    i += n;
+                    j += m;
+                }
+                // 不正なUTF-8のバイトは、同じバイトとのみ等しい
+                (None, None) if group[i] == rest[0] => {
+                    i += 1;
+                    j += 1;
+                }
+                _ => return None,
+            }
+        }
+        Some(j)
+    }
+}
+
+/// bytesの先頭にある1文字分のUTF-8のバイト列を復号し、文字とバイト数を返す。
+///
+/// 不正なバイト列の場合はNoneを返す。
+fn decode_utf8(bytes: &[u8]) -> Option<(char, usize)> {
+    let len = match *bytes.first()? {
+        0x00..=0x7F => 1,
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => return None,
+    };
+    let c = std::str::from_utf8(bytes.get(..len)?)
+        .ok()?
+        .chars()
+        .next()?;
+    Some((c, len))
+}
+
+/// 命令列をengineで評価できるか検査する。
+///
+/// 後方参照やアトミックグループを含む命令列を、それらをサポートしない評価器で
+/// 評価する場合はエラーを返す。
+pub fn check_engine(inst: &[Instruction], engine: Engine) -> Result<(), EvalError> {
+    for i in inst {
+        match i {
+            Instruction::Backref { .. } if !engine.supports_backref() => {
+                return Err(EvalError::UnsupportedBackref(engine));
+            }
+            Instruction::Atomic { .. } if !engine.supports_atomic() => {
+                return Err(EvalError::UnsupportedAtomic(engine));
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// lineの先頭からマッチングを行う。
+pub fn eval<T: Symbol>(
+    inst: &[Instruction],
+    line: &[T],
+    engine: Engine,
+) -> Result<bool, EvalError> {
+    check_engine(inst, engine)?;
+    let mut slots = vec![None; slot_len(inst)];
+    eval_anchored(inst, line, 0, 0, &mut slots, None, engine)
+}
+
+/// lineのsp文字目から、pc番目の命令以降をengineで評価する。
+///
+/// endを指定した場合は、match命令に到達した時点でspがendと等しい場合のみマッチとする。
+fn eval_anchored<T: Symbol>(
+    inst: &[Instruction],
+    line: &[T],
+    pc: usize,
+    sp: usize,
+    slots: &mut [Option<usize>],
+    end: Option<usize>,
+    engine: Engine,
+) -> Result<bool, EvalError> {
+    match engine {
+        Engine::DepthFirst => eval_depth(inst, line, pc, sp, slots, &mut Vec::new(), end),
+        Engine::WidthFirst => eval_width(inst, line, pc, sp, slots, true, end),
+        Engine::Backtrack => match Visited::new(inst, line) {
+            Some(mut visited) => eval_backtrack(inst, line, pc, sp, slots, &mut visited, end),
+            None => eval_width(inst, line, pc, sp, slots, true, end),
+        },
+    }
+}
+
+/// lineの中から最左のマッチを探索し、キャプチャグループの位置を保存したスロットを返す。
+///
+/// 命令列の先頭に暗黙の`.*?`があるものとして評価を行い、最初にマッチした時点のスロットを返す。
+/// 深さ優先探索では開始位置を1文字ずつずらしながら評価を行い、
+/// 幅優先探索では各位置で新たなスレッドを追加しながら1回の走査で評価を行う。
+///
+/// スロットの2n番目と2n+1番目が、n番目のキャプチャグループの開始位置と終了位置を
+/// 文字単位のindexで表す。0番目のグループはマッチ全体の範囲となる。
+pub fn search<T: Symbol>(
+    inst: &[Instruction],
+    line: &[T],
+    engine: Engine,
+) -> Result<Option<Vec<Option<usize>>>, EvalError> {
+    check_engine(inst, engine)?;
+    let mut slots = vec![None; slot_len(inst)];
+    let matched = match engine {
+        Engine::DepthFirst => {
+            let mut matched = false;
+            let mut undo = Vec::new();
+            for start in 0..=line.len() {
+                slots.fill(None);
+                undo.clear();
+                if eval_depth(inst, line, 0, start, &mut slots, &mut undo, None)? {
+                    matched = true;
+                    break;
+                }
+            }
+            matched
+        }
+        Engine::WidthFirst => eval_width(inst, line, 0, 0, &mut slots, false, None)?,
+        Engine::Backtrack => {
+            // ある開始位置で失敗した(pc, sp)は、他の開始位置からでも失敗するため、
+            // 訪問済みの記録は開始位置をずらしても引き継ぐ
+            let Some(mut visited) = Visited::new(inst, line) else {
+                return Ok(eval_width(inst, line, 0, 0, &mut slots, false, None)?.then_some(slots));
+            };
+            let mut matched = false;
+            for start in 0..=line.len() {
+                slots.fill(None);
+                if eval_backtrack(inst, line, 0, start, &mut slots, &mut visited, None)? {
+                    matched = true;
+                    break;
+                }
+            }
+            matched
+        }
+    };
+
+    Ok(if matched { Some(slots) } else { None })
+}
+
+/// 命令列が利用するスロットの数を返す。
+pub fn slot_len(inst: &[Instruction]) -> usize {
+    inst.iter()
+        .filter_map(|i| match i {
+            Instruction::Save(n) => Some(n + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// save命令を実行し、spをn番目のスロットに保存する。
+fn save(slots: &mut [Option<usize>], n: usize, sp: usize) -> Result<(), EvalError> {
+    if let Some(slot) = slots.get_mut(n) {
+        *slot = Some(sp);
+        Ok(())
+    } else {
+        Err(EvalError::InvalidSlot)
+    }
+}
+
+/// progress命令を評価し、n番目のスロットに保存した繰り返しの開始位置から
+/// 入力を消費していない場合はtrueを返す。
+fn is_empty_loop(slots: &[Option<usize>], n: usize, sp: usize) -> Result<bool, EvalError> {
+    if let Some(slot) = slots.get(n) {
+        Ok(*slot == Some(sp))
+    } else {
+        Err(EvalError::InvalidSlot)
+    }
+}
+
+/// progress命令が参照するスロットの番号を返す。
+fn progress_marks(inst: &[Instruction]) -> Vec<usize> {
+    let mut marks: Vec<usize> = inst
+        .iter()
+        .filter_map(|i| match i {
+            Instruction::Progress(n) => Some(*n),
+            _ => None,
+        })
+        .collect();
+    marks.sort_unstable();
+    marks.dedup();
+    marks
+}
+
+/// marksのうち、繰り返しの開始位置としてspが保存されているスロットの番号を返す。
+///
+/// 同じpcとspにあるスレッドでも、このスロットが異なればprogress命令の結果が異なるため、
+/// 重複を除く際はpcとspに加えてこの集合で区別する。その他のスロットは以降の評価に影響しない。
+fn fresh_marks(marks: &[usize], slots: &[Option<usize>], sp: usize) -> Vec<usize> {
+    marks
+        .iter()
+        .copied()
+        .filter(|&n| slots.get(n).copied().flatten() == Some(sp))
+        .collect()
+}
+
+/// backref命令を評価し、lineのsp文字目からgroup番目のキャプチャグループと
+/// 同じ文字列が続く場合は、その文字数を返す。
+///
+/// グループがマッチに参加していない場合はNoneを返す。
+fn match_backref<T: Symbol>(
+    inst: &Instruction,
+    line: &[T],
+    sp: usize,
+    slots: &[Option<usize>],
+) -> Result<Option<usize>, EvalError> {
+    let Instruction::Backref {
+        group,
+        case_insensitive,
+        ascii,
+    } = inst
+    else {
+        return Ok(None);
+    };
+    let start = slots.get(group * 2).ok_or(EvalError::InvalidSlot)?;
+    let end = slots.get(group * 2 + 1).ok_or(EvalError::InvalidSlot)?;
+    let (Some(start), Some(end)) = (*start, *end) else {
+        return Ok(None);
+    };
+    // 繰り返しの中でグループの開始位置のみが更新された場合
+    if start > end {
+        return Ok(None);
+    }
+
+    let group = &line[start..end];
+    let Some(input) = line.get(sp..) else {
+        return Ok(None);
+    };
+    if *case_insensitive {
+        Ok(T::match_ignore_case(group, input, *ascii))
     } else {
-        eval_width(inst, line)
+        Ok(input.starts_with(group).then_some(group.len()))
     }
 }
 
+/// アサーション命令を評価し、lineのsp文字目の位置で条件を満たす場合はtrueを返す。
+///
+/// アサーション命令でない場合はfalseを返す。
+pub(super) fn is_asserted<T: Symbol>(inst: &Instruction, line: &[T], sp: usize) -> bool {
+    match inst {
+        Instruction::AssertStart => sp == 0,
+        Instruction::AssertEnd => sp == line.len(),
+        Instruction::AssertStartLine => sp == 0 || line.get(sp - 1).is_some_and(|c| c.is_newline()),
+        Instruction::AssertEndLine => {
+            sp == line.len() || line.get(sp).is_some_and(|c| c.is_newline())
+        }
+        // 有効なUTF-8の文字の途中では、単語境界であるかを判定しない
+        Instruction::WordBoundary { ascii } => {
+            T::is_char_boundary(line, sp) && is_word_boundary(line, sp, *ascii)
+        }
+        Instruction::NotWordBoundary { ascii } => {
+            T::is_char_boundary(line, sp) && !is_word_boundary(line, sp, *ascii)
+        }
+        _ => false,
+    }
+}
+
+/// lineのsp文字目の位置の前後で、単語構成文字であるかが異なる場合はtrueを返す。
+fn is_word_boundary<T: Symbol>(line: &[T], sp: usize, ascii: bool) -> bool {
+    T::is_word_before(line, sp, ascii) != T::is_word_after(line, sp, ascii)
+}
+
 /// 深さ優先探索で再起的にマッチングを行う評価器
-fn eval_depth(
+///
+/// 変更したスロットの番号と元の値はundoに積み、分岐に失敗した場合はそれを使ってスロットを元に戻す。
+/// endを指定した場合は、match命令に到達した時点でspがendと等しい場合のみマッチとする。
+fn eval_depth<T: Symbol>(
     inst: &[Instruction],
-    line: &[char],
+    line: &[T],
     mut pc: usize,
     mut sp: usize,
+    slots: &mut [Option<usize>],
+    undo: &mut Vec<(usize, Option<usize>)>,
+    end: Option<usize>,
 ) -> Result<bool, EvalError> {
     loop {
         // instに入っている分だけloop
@@ -46,129 +459,557 @@ fn eval_depth(
         };
 
         match next {
-            Instruction::Char(c) => {
-                if let Some(sp_c) = line.get(sp) {
-                    if c == sp_c {
-                        safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
-                        safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
-                    } else {
-                        return Ok(false);
-                    }
+            Instruction::Char(_)
+            | Instruction::Any
+            | Instruction::AnyNotNewline
+            | Instruction::Class(_)
+            | Instruction::Byte(_)
+            | Instruction::ByteRange(..) => {
+                if line.get(sp).is_some_and(|&c| c.is_accepted_by(next)) {
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                    safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
                 } else {
                     return Ok(false);
                 }
             }
-            Instruction::Any => {
-                if let Some(&sp_c) = line.get(sp) {
-                    if sp_c != '\n' {
-                        safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
-                        safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
-                    }
+            Instruction::AssertStart
+            | Instruction::AssertEnd
+            | Instruction::AssertStartLine
+            | Instruction::AssertEndLine
+            | Instruction::WordBoundary { .. }
+            | Instruction::NotWordBoundary { .. } => {
+                if is_asserted(next, line, sp) {
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
                 } else {
                     return Ok(false);
                 }
             }
             Instruction::Match => {
-                return Ok(true);
+                return Ok(end.is_none_or(|end| end == sp));
             }
             Instruction::Jump(addr) => {
                 pc = *addr;
             }
             Instruction::Split(addr1, addr2) => {
-                if eval_depth(inst, line, *addr1, sp)? || eval_depth(inst, line, *addr2, sp)? {
+                // 失敗した場合はスロットを分岐前の状態に戻してから次の分岐を試す
+                let mark = undo.len();
+                if eval_depth(inst, line, *addr1, sp, slots, undo, end)? {
                     return Ok(true);
+                }
+                for (n, old) in undo.drain(mark..).rev() {
+                    slots[n] = old;
+                }
+                pc = *addr2;
+            }
+            Instruction::Save(n) => {
+                let old = slots.get(*n).copied().ok_or(EvalError::InvalidSlot)?;
+                undo.push((*n, old));
+                save(slots, *n, sp)?;
+                safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+            }
+            Instruction::Progress(n) => {
+                if is_empty_loop(slots, *n, sp)? {
+                    return Ok(false);
+                }
+                safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+            }
+            Instruction::Backref { .. } => {
+                if let Some(len) = match_backref(next, line, sp, slots)? {
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                    safe_add(&mut sp, &len, || EvalError::SPOverFlow)?;
+                } else {
+                    return Ok(false);
+                }
+            }
+            Instruction::Look { look, next } => {
+                let mut sub = slots.to_vec();
+                if eval_look(inst, line, *look, pc, sp, &mut sub, Engine::DepthFirst)? {
+                    update_slots(slots, sub, |n, old| undo.push((n, old)));
+                    pc = *next;
                 } else {
                     return Ok(false);
                 }
             }
+            Instruction::Atomic { slot, next } => {
+                if let Some(end) = eval_atomic(inst, line, pc, sp, slots, undo, *slot)? {
+                    pc = *next;
+                    sp = end;
+                } else {
+                    return Ok(false);
+                }
+            }
+            Instruction::Cut => {
+                // 部分プログラムのマッチに成功したため、呼び出し元のatomic命令に戻る
+                return Ok(true);
+            }
+        }
+    }
+}
+
+/// pc番目のatomic命令に続く部分プログラムを深さ優先探索で評価し、
+/// lineのsp文字目から最初に見つかったマッチの終了位置を返す。
+///
+/// マッチした場合は部分プログラム内でキャプチャした位置がslotsに残り、その変更はundoに積まれる。
+/// 残りの分岐は評価しないため、後続の命令が失敗してもアトミックグループの中には戻らない。
+fn eval_atomic<T: Symbol>(
+    inst: &[Instruction],
+    line: &[T],
+    pc: usize,
+    sp: usize,
+    slots: &mut [Option<usize>],
+    undo: &mut Vec<(usize, Option<usize>)>,
+    slot: usize,
+) -> Result<Option<usize>, EvalError> {
+    let mut body = pc;
+    safe_add(&mut body, &1, || EvalError::PCOverFlow)?;
+
+    if !eval_depth(inst, line, body, sp, slots, undo, None)? {
+        return Ok(None);
+    }
+    let end = slots
+        .get(slot)
+        .copied()
+        .flatten()
+        .ok_or(EvalError::InvalidSlot)?;
+    Ok(Some(end))
+}
+
+/// pc番目のlook命令に続く部分プログラムを呼び出し元と同じengineで評価し、
+/// lineのsp文字目の位置で先読み・後読みが成功した場合はtrueを返す。
+///
+/// 後読みでは、部分プログラムの長さの範囲内で開始位置を変えながら、spで終わるマッチを探す。
+/// 否定でない先読み・後読みが成功した場合は、部分プログラム内でキャプチャした位置をslotsに反映する。
+fn eval_look<T: Symbol>(
+    inst: &[Instruction],
+    line: &[T],
+    look: Look,
+    pc: usize,
+    sp: usize,
+    slots: &mut [Option<usize>],
+    engine: Engine,
+) -> Result<bool, EvalError> {
+    let mut body = pc;
+    safe_add(&mut body, &1, || EvalError::PCOverFlow)?;
+
+    let (negated, matched) = match look {
+        Look::Ahead { negated } => {
+            let mut sub = slots.to_vec();
+            let matched = eval_anchored(inst, line, body, sp, &mut sub, None, engine)?;
+            (negated, matched.then_some(sub))
+        }
+        Look::Behind { negated, min, max } => {
+            let mut matched = None;
+            for len in min..=max.min(sp) {
+                let mut sub = slots.to_vec();
+                if eval_anchored(inst, line, body, sp - len, &mut sub, Some(sp), engine)? {
+                    matched = Some(sub);
+                    break;
+                }
+            }
+            (negated, matched)
+        }
+    };
+
+    match matched {
+        Some(sub) if !negated => {
+            slots.copy_from_slice(&sub);
+            Ok(true)
+        }
+        Some(_) => Ok(false),
+        None => Ok(negated),
+    }
+}
+
+/// 部分プログラムの評価後のスロットsubをslotsに反映する。
+///
+/// 値が変わったスロットごとに、番号と元の値をrestoreに渡す。
+fn update_slots(
+    slots: &mut [Option<usize>],
+    sub: Vec<Option<usize>>,
+    mut restore: impl FnMut(usize, Option<usize>),
+) {
+    for (n, (old, new)) in slots.iter_mut().zip(sub).enumerate() {
+        if *old != new {
+            restore(n, *old);
+            *old = new;
+        }
+    }
+}
+
+/// Pike VMのスレッド。
+///
+/// 入力を消費する命令（char, any, match）を指すpcと、そこに至るまでに保存したスロットを持つ。
+struct Thread {
+    pc: usize,
+    slots: Vec<Option<usize>>,
+}
+
+/// 優先度順に並んだスレッドのリスト。
+///
+/// 同じpcのスレッドは、繰り返しの開始位置が現在の位置と等しいスロットの集合ごとに1つしか追加されない。
+/// 空文字列にマッチし得る繰り返しが入れ子になっていなければ、リストの長さは命令数の定数倍で抑えられる。
+struct ThreadList {
+    threads: Vec<Thread>,
+    visited: Vec<bool>, // 開始位置が現在の位置と等しいスロットがないpc
+    visited_fresh: HashSet<(usize, Vec<usize>)>, // pcと、開始位置が現在の位置と等しいスロットの組
+    marks: Vec<usize>,  // progress命令が参照するスロット
+}
+
+impl ThreadList {
+    fn new(inst: &[Instruction]) -> Self {
+        ThreadList {
+            threads: Vec::with_capacity(inst.len()),
+            visited: vec![false; inst.len()],
+            visited_fresh: HashSet::new(),
+            marks: progress_marks(inst),
+        }
+    }
+
+    /// spの位置にあるスレッドのpcを訪問済みにする。既に訪問済みだった場合はfalseを返す。
+    fn insert(&mut self, pc: usize, sp: usize, slots: &[Option<usize>]) -> Result<bool, EvalError> {
+        let fresh = fresh_marks(&self.marks, slots, sp);
+        if !fresh.is_empty() {
+            return Ok(self.visited_fresh.insert((pc, fresh)));
         }
+        match self.visited.get_mut(pc) {
+            Some(visited) => Ok(!std::mem::replace(visited, true)),
+            None => Err(EvalError::InvalidPC),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.threads.clear();
+        self.visited.fill(false);
+        self.visited_fresh.clear();
     }
 }
 
-fn pop_ctx(
-    pc: &mut usize,
-    sp: &mut usize,
-    ctx: &mut VecDeque<(usize, usize)>,
+/// add_thread関数で利用する、探索スタックに積む処理
+enum Job {
+    /// pcから到達できるスレッドを追加
+    Explore(usize),
+    /// n番目のスロットを元の値に戻す
+    Restore(usize, Option<usize>),
+}
+
+/// pcから入力を消費せずに到達できるスレッドを、優先度順にlistへ追加する。
+///
+/// jump, split, save, progress命令とアサーション命令、look命令はここで辿り、既に訪れたpcは無視する。
+/// ただし、繰り返しの開始位置が現在の位置と等しいスロットの集合が異なる場合は、別の状態として辿る。
+fn add_thread<T: Symbol>(
+    inst: &[Instruction],
+    line: &[T],
+    list: &mut ThreadList,
+    pc: usize,
+    sp: usize,
+    slots: &mut [Option<usize>],
 ) -> Result<(), EvalError> {
-    if let Some((p, s)) = ctx.pop_back() {
-        *pc = p;
-        *sp = s;
-        Ok(())
-    } else {
-        return Err(EvalError::InvalidContext);
+    let mut stack = vec![Job::Explore(pc)];
+
+    while let Some(job) = stack.pop() {
+        let pc = match job {
+            Job::Explore(pc) => pc,
+            Job::Restore(n, old) => {
+                slots[n] = old;
+                continue;
+            }
+        };
+
+        if !list.insert(pc, sp, slots)? {
+            continue;
+        }
+
+        match &inst[pc] {
+            Instruction::Jump(addr) => stack.push(Job::Explore(*addr)),
+            Instruction::Split(addr1, addr2) => {
+                // addr1を優先するため、後に積む
+                stack.push(Job::Explore(*addr2));
+                stack.push(Job::Explore(*addr1));
+            }
+            Instruction::Save(n) => {
+                let old = slots.get(*n).copied().ok_or(EvalError::InvalidSlot)?;
+                stack.push(Job::Restore(*n, old));
+                save(slots, *n, sp)?;
+
+                let mut next = pc;
+                safe_add(&mut next, &1, || EvalError::PCOverFlow)?;
+                stack.push(Job::Explore(next));
+            }
+            Instruction::Progress(n) => {
+                if !is_empty_loop(slots, *n, sp)? {
+                    let mut next = pc;
+                    safe_add(&mut next, &1, || EvalError::PCOverFlow)?;
+                    stack.push(Job::Explore(next));
+                }
+            }
+            inst @ (Instruction::AssertStart
+            | Instruction::AssertEnd
+            | Instruction::AssertStartLine
+            | Instruction::AssertEndLine
+            | Instruction::WordBoundary { .. }
+            | Instruction::NotWordBoundary { .. }) => {
+                if is_asserted(inst, line, sp) {
+                    let mut next = pc;
+                    safe_add(&mut next, &1, || EvalError::PCOverFlow)?;
+                    stack.push(Job::Explore(next));
+                }
+            }
+            Instruction::Look { look, next } => {
+                // 部分プログラムも幅優先探索で評価し、キャプチャした位置は後で元に戻す
+                let mut sub = slots.to_vec();
+                if eval_look(inst, line, *look, pc, sp, &mut sub, Engine::WidthFirst)? {
+                    update_slots(slots, sub, |n, old| stack.push(Job::Restore(n, old)));
+                    stack.push(Job::Explore(*next));
+                }
+            }
+            _ => list.threads.push(Thread {
+                pc,
+                slots: slots.to_vec(),
+            }),
+        }
     }
+
+    Ok(())
 }
 
-/// 幅優先探索で再起的にマッチングを行う評価器
-fn eval_width(inst: &[Instruction], line: &[char]) -> Result<bool, EvalError> {
-    let mut ctx = VecDeque::new();
-    let mut pc = 0;
-    let mut sp = 0;
+/// Pike VMによって幅優先探索でマッチングを行う評価器
+///
+/// lineのstart文字目から、pc番目の命令以降を評価する。
+/// スレッドのリストを入力1文字ごとに同時に進める。
+/// 各位置でのスレッドはpcで重複が除かれるため、計算量はO(入力長 × 命令数)となる。
+/// ただし、空文字列にマッチし得る繰り返しの中では、progress命令の結果が変わらないよう
+/// 繰り返しの開始位置が現在の位置と等しいスロットの集合でもスレッドを区別する。
+/// ただし先読み・後読みの部分プログラムは各位置で評価し直すため、
+/// look命令を含む場合の計算量はO(入力長² × 命令数)となる。
+/// スレッドは優先度順に並んでいるため、深さ優先探索と同じマッチが得られる。
+///
+/// anchoredがfalseの場合は、まだマッチが見つかっていない間、各位置で新たなスレッドを
+/// 最も低い優先度で追加することで、最左のマッチを探索する。
+/// endを指定した場合は、spがendと等しい位置でのみマッチとする。
+fn eval_width<T: Symbol>(
+    inst: &[Instruction],
+    line: &[T],
+    pc: usize,
+    start: usize,
+    slots: &mut [Option<usize>],
+    anchored: bool,
+    end: Option<usize>,
+) -> Result<bool, EvalError> {
+    let mut clist = ThreadList::new(inst);
+    let mut nlist = ThreadList::new(inst);
+    let mut matched = false;
+    let last = end.unwrap_or(line.len()).min(line.len());
 
-    loop {
-        let next = if let Some(i) = inst.get(pc) {
-            i
-        } else {
+    for sp in start..=last {
+        if !matched && (sp == start || !anchored) {
+            let mut init = if sp == start {
+                slots.to_vec()
+            } else {
+                vec![None; slots.len()]
+            };
+            add_thread(inst, line, &mut clist, pc, sp, &mut init)?;
+        }
+
+        // 生きているスレッドがなく、新たなスレッドも追加されない場合は終了
+        if clist.threads.is_empty() && (matched || anchored) {
+            break;
+        }
+
+        for th in clist.threads.iter_mut() {
+            let consumed = match &inst[th.pc] {
+                i @ (Instruction::Char(_)
+                | Instruction::Any
+                | Instruction::AnyNotNewline
+                | Instruction::Class(_)
+                | Instruction::Byte(_)
+                | Instruction::ByteRange(..)) => line.get(sp).is_some_and(|&c| c.is_accepted_by(i)),
+                Instruction::Match if end.is_some_and(|end| end != sp) => false,
+                Instruction::Match => {
+                    // 優先度の低いスレッドは破棄する
+                    slots.copy_from_slice(&th.slots);
+                    matched = true;
+                    break;
+                }
+                _ => return Err(EvalError::InvalidPC),
+            };
+
+            if consumed {
+                let mut next = th.pc;
+                safe_add(&mut next, &1, || EvalError::PCOverFlow)?;
+                add_thread(inst, line, &mut nlist, next, sp + 1, &mut th.slots)?;
+            }
+        }
+
+        std::mem::swap(&mut clist, &mut nlist);
+        nlist.clear();
+    }
+
+    Ok(matched)
+}
+
+/// Backtrackが訪問済みの(pc, sp)の記録に用いるビット数の上限（256KiB）。
+pub const MAX_VISITED_BITS: usize = 256 * 1024 * 8;
+
+/// 訪問済みの(pc, sp)を記録するビット集合
+///
+/// 繰り返しの開始位置がspと等しいスロットがある場合は、その集合も含めて別に記録する。
+struct Visited {
+    bits: Vec<u64>,
+    stride: usize,
+    fresh: HashSet<(usize, usize, Vec<usize>)>,
+    marks: Vec<usize>, // progress命令が参照するスロット
+}
+
+impl Visited {
+    /// 記録に必要なビット数が[`MAX_VISITED_BITS`]を超える場合はNoneを返す。
+    fn new<T>(inst: &[Instruction], line: &[T]) -> Option<Self> {
+        let stride = line.len() + 1;
+        let len = inst
+            .len()
+            .checked_mul(stride)
+            .filter(|&len| len <= MAX_VISITED_BITS)?;
+        Some(Visited {
+            bits: vec![0; len.div_ceil(64)],
+            stride,
+            fresh: HashSet::new(),
+            marks: progress_marks(inst),
+        })
+    }
+
+    /// (pc, sp)を訪問済みにする。既に訪問済みだった場合はfalseを返す。
+    fn insert(&mut self, pc: usize, sp: usize, slots: &[Option<usize>]) -> Result<bool, EvalError> {
+        let fresh = fresh_marks(&self.marks, slots, sp);
+        if !fresh.is_empty() {
+            return Ok(self.fresh.insert((pc, sp, fresh)));
+        }
+        let i = pc * self.stride + sp;
+        let Some(word) = self.bits.get_mut(i / 64) else {
             return Err(EvalError::InvalidPC);
         };
+        let mask = 1 << (i % 64);
+        if *word & mask != 0 {
+            Ok(false)
+        } else {
+            *word |= mask;
+            Ok(true)
+        }
+    }
+}
 
-        match next {
-            Instruction::Char(c) => {
-                if let Some(sp_c) = line.get(sp) {
-                    if c == sp_c {
+/// eval_backtrack関数で利用する、バックトラック用のスタックに積む処理
+enum Frame {
+    /// (pc, sp)から評価を再開
+    Step(usize, usize),
+    /// n番目のスロットを元の値に戻す
+    Restore(usize, Option<usize>),
+}
+
+/// 訪問済みの(pc, sp)を記録しながら、深さ優先探索でマッチングを行う評価器
+///
+/// lineのstart文字目から、pc番目の命令以降を評価する。
+/// eval_depthと同じ優先度でマッチを探索するが、一度失敗した(pc, sp)は再び評価しない。
+/// そのため計算量はO(入力長 × 命令数)に抑えられる。
+/// eval_widthと同様に、空文字列にマッチし得る繰り返しの中では
+/// 繰り返しの開始位置がspと等しいスロットの集合でも状態を区別する。
+/// ただし先読み・後読みの部分プログラムは各位置で評価し直すため、
+/// look命令を含む場合の計算量はO(入力長² × 命令数)となる。
+/// 再帰の代わりに明示的なスタックを用いるため、ネイティブのスタックも溢れない。
+///
+/// endを指定した場合は、match命令に到達した時点でspがendと等しい場合のみマッチとする。
+fn eval_backtrack<T: Symbol>(
+    inst: &[Instruction],
+    line: &[T],
+    pc: usize,
+    start: usize,
+    slots: &mut [Option<usize>],
+    visited: &mut Visited,
+    end: Option<usize>,
+) -> Result<bool, EvalError> {
+    let mut stack = vec![Frame::Step(pc, start)];
+
+    while let Some(frame) = stack.pop() {
+        let (mut pc, mut sp) = match frame {
+            Frame::Step(pc, sp) => (pc, sp),
+            Frame::Restore(n, old) => {
+                slots[n] = old;
+                continue;
+            }
+        };
+
+        loop {
+            if !visited.insert(pc, sp, slots)? {
+                break;
+            }
+
+            match &inst[pc] {
+                i @ (Instruction::Char(_)
+                | Instruction::Any
+                | Instruction::AnyNotNewline
+                | Instruction::Class(_)
+                | Instruction::Byte(_)
+                | Instruction::ByteRange(..)) => {
+                    if line.get(sp).is_some_and(|&c| c.is_accepted_by(i)) {
                         safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
                         safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
                     } else {
-                        if ctx.is_empty() {
-                            return Ok(false);
-                        } else {
-                            pop_ctx(&mut pc, &mut sp, &mut ctx)?;
-                        }
-                    }
-                } else {
-                    if ctx.is_empty() {
-                        return Ok(false);
-                    } else {
-                        pop_ctx(&mut pc, &mut sp, &mut ctx)?;
+                        break;
                     }
                 }
-            }
-            Instruction::Any => {
-                if let Some(&sp_c) = line.get(sp) {
-                    if sp_c != '\n' {
+                inst @ (Instruction::AssertStart
+                | Instruction::AssertEnd
+                | Instruction::AssertStartLine
+                | Instruction::AssertEndLine
+                | Instruction::WordBoundary { .. }
+                | Instruction::NotWordBoundary { .. }) => {
+                    if is_asserted(inst, line, sp) {
                         safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
-                        safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
                     } else {
-                        if ctx.is_empty() {
-                            return Ok(false);
-                        } else {
-                            pop_ctx(&mut pc, &mut sp, &mut ctx)?;
-                        }
+                        break;
                     }
-                } else {
-                    if ctx.is_empty() {
-                        return Ok(false);
-                    } else {
-                        pop_ctx(&mut pc, &mut sp, &mut ctx)?;
+                }
+                Instruction::Match => {
+                    if end.is_none_or(|end| end == sp) {
+                        return Ok(true);
                     }
+                    break;
+                }
+                Instruction::Jump(addr) => {
+                    pc = *addr;
+                }
+                Instruction::Split(addr1, addr2) => {
+                    stack.push(Frame::Step(*addr2, sp));
+                    pc = *addr1;
+                }
+                Instruction::Save(n) => {
+                    let old = slots.get(*n).copied().ok_or(EvalError::InvalidSlot)?;
+                    stack.push(Frame::Restore(*n, old));
+                    save(slots, *n, sp)?;
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                }
+                Instruction::Progress(n) => {
+                    if is_empty_loop(slots, *n, sp)? {
+                        break;
+                    }
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                }
+                Instruction::Backref { .. } => {
+                    return Err(EvalError::UnsupportedBackref(Engine::Backtrack));
+                }
+                Instruction::Atomic { .. } | Instruction::Cut => {
+                    return Err(EvalError::UnsupportedAtomic(Engine::Backtrack));
+                }
+                Instruction::Look { look, next } => {
+                    // 部分プログラムもバックトラックで評価し、キャプチャした位置は後で元に戻す
+                    let mut sub = slots.to_vec();
+                    if !eval_look(inst, line, *look, pc, sp, &mut sub, Engine::Backtrack)? {
+                        break;
+                    }
+                    update_slots(slots, sub, |n, old| stack.push(Frame::Restore(n, old)));
+                    pc = *next;
                 }
-            }
-            Instruction::Match => {
-                return Ok(true);
-            }
-            Instruction::Jump(addr) => {
-                pc = *addr;
-            }
-            Instruction::Split(addr1, addr2) => {
-                pc = *addr1;
-                ctx.push_back((*addr2, sp));
-                continue;
             }
         }
-
-        if !ctx.is_empty() {
-            ctx.push_back((pc, sp));
-            pop_ctx(&mut pc, &mut sp, &mut ctx)?;
-        }
     }
+
+    Ok(false)
 }
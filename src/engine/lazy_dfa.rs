@@ -0,0 +1,312 @@
+//! 命令列の状態を必要になった時点で決定化する、遅延DFA。
+use super::{Instruction, class::is_word_char};
+use std::{collections::HashMap, sync::Mutex};
+
+/// キャッシュに保持する状態数の上限のデフォルト値。
+pub const DEFAULT_CACHE_CAPACITY: usize = 4096;
+
+/// 1回の評価でキャッシュの破棄がこの回数を超えた場合は、評価を諦めてNFAに任せる。
+const MAX_CACHE_CLEARS: usize = 8;
+
+// 位置の前後にある文字の種類を表すフラグ。アサーション命令の評価に用いる。
+pub(super) const EDGE: u8 = 1; // 入力の先頭または末尾
+pub(super) const NEWLINE: u8 = 1 << 1;
+pub(super) const WORD_ASCII: u8 = 1 << 2;
+pub(super) const WORD_UNICODE: u8 = 1 << 3;
+
+/// 文字の種類を表すフラグを返す。cがNoneの場合は入力の先頭または末尾を表す。
+fn char_flags(c: Option<char>) -> u8 {
+    let Some(c) = c else {
+        return EDGE;
+    };
+    let mut flags = 0;
+    if c == '\n' {
+        flags |= NEWLINE;
+    }
+    if is_word_char(c, true) {
+        flags |= WORD_ASCII;
+    }
+    if is_word_char(c, false) {
+        flags |= WORD_UNICODE;
+    }
+    flags
+}
+
+/// DFAの状態を識別するキー。
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct StateKey {
+    pub(super) pcs: Vec<usize>, // 入力を消費した直後の、ε遷移を辿る前のpcの集合（昇順）
+    pub(super) prev: u8,        // 直前の文字の種類
+    pub(super) anchored: bool,  // falseの場合は、各位置で新たにマッチを開始する
+}
+
+/// DFAの状態。
+#[derive(Debug)]
+struct State {
+    key: StateKey,
+    next: HashMap<char, (usize, bool)>, // 文字ごとの遷移先と、その文字の手前でマッチするか
+    eoi: Option<bool>,                  // 入力の末尾でマッチするか
+}
+
+/// 決定化した状態のキャッシュ。
+#[derive(Debug, Default)]
+struct Cache {
+    states: Vec<State>,
+    index: HashMap<StateKey, usize>,
+}
+
+impl Cache {
+    /// keyの状態を返す。キャッシュにない場合は追加する。
+    fn get_or_insert(&mut self, key: StateKey) -> usize {
+        if let Some(&id) = self.index.get(&key) {
+            return id;
+        }
+        let id = self.states.len();
+        self.index.insert(key.clone(), id);
+        self.states.push(State {
+            key,
+            next: HashMap::new(),
+            eoi: None,
+        });
+        id
+    }
+
+    fn clear(&mut self) {
+        self.states.clear();
+        self.index.clear();
+    }
+}
+
+/// 遅延DFA。
+///
+/// 入力を1文字読むごとに、NFAの命令列のpcの集合を1つの状態として決定化し、
+/// 遷移をキャッシュする。キャッシュした遷移は以降の評価で再利用されるため、
+/// 同じ正規表現で繰り返しマッチングを行う場合は、1文字あたり表を1回引くだけで済む。
+///
+/// マッチしたかどうかのみを判定し、キャプチャグループの位置は求めない。
+/// キャッシュの状態数が上限に達した場合はキャッシュを破棄して評価を続けるが、
+/// 破棄が繰り返される場合は評価を諦め、NFAによる評価器に任せる。
+#[derive(Debug)]
+pub struct LazyDfa {
+    capacity: usize,
+    cache: Mutex<Cache>,
+}
+
+impl LazyDfa {
+    /// 命令列から遅延DFAを生成する。
+    ///
+    /// 後方参照、先読み・後読み、アトミックグループを含む命令列は決定化できないため、Noneを返す。
+    pub fn new(inst: &[Instruction], capacity: usize) -> Option<LazyDfa> {
+        if !is_supported(inst) || capacity == 0 {
+            return None;
+        }
+        Some(LazyDfa {
+            capacity,
+            cache: Mutex::new(Cache::default()),
+        })
+    }
+
+    /// lineが命令列にマッチするか判定する。
+    ///
+    /// anchoredがtrueの場合はlineの先頭からのマッチのみを、
+    /// falseの場合はいずれかの位置から始まるマッチを探す。
+    /// キャッシュの破棄が繰り返され、評価を諦めた場合はNoneを返す。
+    pub fn is_match(&self, inst: &[Instruction], line: &[char], anchored: bool) -> Option<bool> {
+        let mut matched = false;
+        self.scan(inst, line, anchored, |_| {
+            matched = true;
+            true
+        })?;
+        Some(matched)
+    }
+
+    /// lineのいずれかの位置から始まるマッチのうち、最も後ろで終わるマッチの終了位置を返す。
+    ///
+    /// 逆順にした命令列と入力に用いると、最左のマッチの開始位置が求まる。
+    /// マッチしない場合はSome(None)を、評価を諦めた場合はNoneを返す。
+    pub fn last_match_end(&self, inst: &[Instruction], line: &[char]) -> Option<Option<usize>> {
+        let mut last = None;
+        self.scan(inst, line, false, |sp| {
+            last = Some(sp);
+            false
+        })?;
+        Some(last)
+    }
+
+    /// lineを走査し、マッチが終わる位置spごとにon_matchを呼ぶ。
+    ///
+    /// on_matchがtrueを返した場合は走査を終える。評価を諦めた場合はNoneを返す。
+    fn scan(
+        &self,
+        inst: &[Instruction],
+        line: &[char],
+        anchored: bool,
+        mut on_match: impl FnMut(usize) -> bool,
+    ) -> Option<()> {
+        let mut cache = self.cache.lock().ok()?;
+        let mut clears = 0;
+
+        let start = StateKey {
+            pcs: vec![0],
+            prev: EDGE,
+            anchored,
+        };
+        let mut id = cache.get_or_insert(start);
+
+        for (sp, &c) in line.iter().enumerate() {
+            let (next, matched) = match cache.states[id].next.get(&c) {
+                Some(&transition) => transition,
+                None => {
+                    let (key, matched) = step(inst, &cache.states[id].key, Some(c));
+                    let key = key?;
+                    // 遷移先がキャッシュにない場合のみ、状態数の上限を確認する
+                    if !cache.index.contains_key(&key) && cache.states.len() >= self.capacity {
+                        // キャッシュを破棄し、現在の状態から決定化をやり直す
+                        clears += 1;
+                        if clears > MAX_CACHE_CLEARS {
+                            return None;
+                        }
+                        let current = cache.states[id].key.clone();
+                        cache.clear();
+                        id = cache.get_or_insert(current);
+                    }
+                    let next = cache.get_or_insert(key);
+                    cache.states[id].next.insert(c, (next, matched));
+                    (next, matched)
+                }
+            };
+
+            if matched && on_match(sp) {
+                return Some(());
+            }
+            id = next;
+            // 生きているスレッドがない場合は、以降でマッチすることはない
+            if cache.states[id].key.pcs.is_empty() {
+                return Some(());
+            }
+        }
+
+        let state = &mut cache.states[id];
+        let matched = match state.eoi {
+            Some(matched) => matched,
+            None => {
+                let (_, matched) = step(inst, &state.key, None);
+                state.eoi = Some(matched);
+                matched
+            }
+        };
+        if matched {
+            on_match(line.len());
+        }
+        Some(())
+    }
+}
+
+/// 命令列を決定化できるか判定する。
+///
+/// 後方参照、先読み・後読み、アトミックグループを含む場合はfalseを返す。
+pub(super) fn is_supported(inst: &[Instruction]) -> bool {
+    inst.iter().all(|i| {
+        !matches!(
+            i,
+            Instruction::Backref { .. }
+                | Instruction::Look { .. }
+                | Instruction::Atomic { .. }
+                | Instruction::Cut
+        )
+    })
+}
+
+/// keyの状態から、次の文字cを読んだ場合の遷移先を求める。
+///
+/// cの手前の位置でマッチするかを合わせて返す。cがNoneの場合は入力の末尾を表し、遷移先はNoneとなる。
+pub(super) fn step(
+    inst: &[Instruction],
+    key: &StateKey,
+    c: Option<char>,
+) -> (Option<StateKey>, bool) {
+    let next_flags = char_flags(c);
+    let closure = epsilon_closure(inst, &key.pcs, key.prev, next_flags);
+    let matched = closure
+        .iter()
+        .any(|&pc| matches!(inst[pc], Instruction::Match));
+
+    let Some(c) = c else {
+        return (None, matched);
+    };
+
+    let mut pcs: Vec<usize> = closure
+        .into_iter()
+        .filter(|&pc| match &inst[pc] {
+            Instruction::Char(ch) => *ch == c,
+            Instruction::Any => true,
+            Instruction::AnyNotNewline => c != '\n',
+            Instruction::Class(class) => class.contains(c),
+            _ => false,
+        })
+        .map(|pc| pc + 1)
+        .collect();
+    if !key.anchored {
+        pcs.push(0);
+    }
+    pcs.sort_unstable();
+    pcs.dedup();
+
+    let key = StateKey {
+        pcs,
+        prev: next_flags,
+        anchored: key.anchored,
+    };
+    (Some(key), matched)
+}
+
+/// pcsから入力を消費せずに到達できる、入力を消費する命令とmatch命令のpcを返す。
+///
+/// アサーション命令は、直前の文字の種類prevと次の文字の種類nextで評価する。
+/// progress命令は、同じpcを二度辿らないことで空の繰り返しが除かれるため、無条件に辿る。
+fn epsilon_closure(inst: &[Instruction], pcs: &[usize], prev: u8, next: u8) -> Vec<usize> {
+    let mut visited = vec![false; inst.len()];
+    let mut closure = Vec::new();
+    let mut stack: Vec<usize> = pcs.iter().rev().copied().collect();
+
+    while let Some(pc) = stack.pop() {
+        match visited.get_mut(pc) {
+            Some(true) | None => continue,
+            Some(visited) => *visited = true,
+        }
+
+        let asserted = match &inst[pc] {
+            Instruction::Jump(addr) => {
+                stack.push(*addr);
+                continue;
+            }
+            Instruction::Split(addr1, addr2) => {
+                stack.push(*addr2);
+                stack.push(*addr1);
+                continue;
+            }
+            Instruction::Save(_) | Instruction::Progress(_) => true,
+            Instruction::AssertStart => prev & EDGE != 0,
+            Instruction::AssertEnd => next & EDGE != 0,
+            Instruction::AssertStartLine => prev & (EDGE | NEWLINE) != 0,
+            Instruction::AssertEndLine => next & (EDGE | NEWLINE) != 0,
+            Instruction::WordBoundary { ascii } => is_word_boundary(prev, next, *ascii),
+            Instruction::NotWordBoundary { ascii } => !is_word_boundary(prev, next, *ascii),
+            _ => {
+                closure.push(pc);
+                continue;
+            }
+        };
+        if asserted {
+            stack.push(pc + 1);
+        }
+    }
+
+    closure
+}
+
+/// 前後の文字の種類から、単語境界であるかを判定する。
+fn is_word_boundary(prev: u8, next: u8, ascii: bool) -> bool {
+    let word = if ascii { WORD_ASCII } else { WORD_UNICODE };
+    (prev & word != 0) != (next & word != 0)
+}
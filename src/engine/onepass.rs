@@ -0,0 +1,237 @@
+//! 各位置で継続できるスレッドが高々1つである命令列を、1回の走査で評価する評価器。
+use super::{Instruction, evaluator, lazy_dfa};
+
+/// ε遷移の経路上で行う動作。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Save(usize),   // spをスロットに保存する
+    Assert(usize), // pcのアサーション命令を評価する
+}
+
+/// 入力を消費した直後の位置から、ε遷移で到達できる命令とその経路。
+#[derive(Debug)]
+struct State {
+    consumers: Vec<(usize, Vec<Action>)>, // 入力を消費する命令のpcと経路（優先度順）
+    matched: Option<(Vec<Action>, usize)>, // match命令への経路と、それより優先される命令の数
+}
+
+/// one-passな命令列の評価器。
+///
+/// 入力を消費する命令の直後から、ε遷移で到達できる命令を事前に求めておく。
+/// one-passな命令列では、次の文字を消費できる命令が高々1つに定まるため、
+/// スレッドのリストやバックトラックを用いず、入力を1回走査するだけでキャプチャグループの位置を求められる。
+///
+/// 評価は与えられた開始位置からのみ行う。開始位置をずらしながら評価すると入力の長さの2乗の時間がかかるため、
+/// 入力の先頭にアンカーされていない命令列では、呼び出し側で最左のマッチの開始位置を求めておく。
+#[derive(Debug)]
+pub struct OnePass {
+    states: Vec<Option<State>>, // 起点のpcごとの状態
+    slot_len: usize,
+    anchored: bool, // 入力の先頭にアンカーされているか
+}
+
+impl OnePass {
+    /// 命令列がone-passであれば、評価器を生成する。
+    ///
+    /// 次の文字を消費できる命令が複数ある場合や、同じ命令に複数の経路で到達する場合はone-passではなく、Noneを返す。
+    /// 後方参照、先読み・後読み、アトミックグループを含む場合もNoneを返す。
+    pub fn new(inst: &[Instruction]) -> Option<OnePass> {
+        if !lazy_dfa::is_supported(inst) {
+            return None;
+        }
+
+        let anchored = is_anchored(inst, &closure(inst, 0)?);
+
+        let mut states: Vec<Option<State>> = (0..inst.len()).map(|_| None).collect();
+        let mut stack = vec![0];
+        while let Some(pc) = stack.pop() {
+            if states.get(pc)?.is_some() {
+                continue;
+            }
+            let state = closure(inst, pc)?;
+            stack.extend(state.consumers.iter().map(|(consumer, _)| consumer + 1));
+            states[pc] = Some(state);
+        }
+
+        Some(OnePass {
+            states,
+            slot_len: evaluator::slot_len(inst),
+            anchored,
+        })
+    }
+
+    /// 命令列が入力の先頭にアンカーされており、マッチが入力の先頭からのみ始まる場合にtrueを返す。
+    pub fn is_anchored(&self) -> bool {
+        self.anchored
+    }
+
+    /// lineのstart文字目から始まるマッチを探索し、キャプチャグループの位置を保存したスロットを返す。
+    ///
+    /// 優先度の低いmatch命令に到達していた場合は、以降で失敗してもその時点のスロットを返す。
+    /// スロットの形式は[`evaluator::search`]と同じ。
+    pub fn search(
+        &self,
+        inst: &[Instruction],
+        line: &[char],
+        start: usize,
+    ) -> Option<Vec<Option<usize>>> {
+        let mut slots = vec![None; self.slot_len];
+        let mut last_match = None;
+        let mut pc = 0;
+        let mut sp = start;
+
+        loop {
+            let state = self.states.get(pc)?.as_ref()?;
+            let mut consumers = state.consumers.as_slice();
+
+            if let Some((actions, priority)) = &state.matched
+                && is_asserted(inst, line, sp, actions)
+            {
+                let mut matched = slots.clone();
+                save(actions, &mut matched, sp);
+                if *priority == 0 {
+                    return Some(matched);
+                }
+                // match命令より優先度の低い命令には進まない
+                last_match = Some(matched);
+                consumers = &consumers[..*priority];
+            }
+
+            let Some(&c) = line.get(sp) else {
+                break;
+            };
+            let Some((consumer, actions)) = consumers
+                .iter()
+                .find(|(consumer, _)| accepts(&inst[*consumer], c))
+            else {
+                break;
+            };
+            if !is_asserted(inst, line, sp, actions) {
+                break;
+            }
+            save(actions, &mut slots, sp);
+            pc = consumer + 1;
+            sp += 1;
+        }
+
+        last_match
+    }
+}
+
+/// startから入力を消費せずに到達できる、入力を消費する命令とmatch命令への経路を求める。
+///
+/// one-passでない場合はNoneを返す。
+fn closure(inst: &[Instruction], start: usize) -> Option<State> {
+    let mut visited = vec![false; inst.len()];
+    let mut consumers = Vec::new();
+    let mut matched = None;
+
+    // 優先度の高い経路から辿る
+    let mut stack = vec![(start, Vec::new())];
+    while let Some((pc, mut actions)) = stack.pop() {
+        if std::mem::replace(visited.get_mut(pc)?, true) {
+            return None;
+        }
+
+        match inst.get(pc)? {
+            Instruction::Char(_)
+            | Instruction::Any
+            | Instruction::AnyNotNewline
+            | Instruction::Class(_) => consumers.push((pc, actions)),
+            Instruction::Match => matched = Some((actions, consumers.len())),
+            Instruction::Jump(addr) => stack.push((*addr, actions)),
+            Instruction::Split(addr1, addr2) => {
+                stack.push((*addr2, actions.clone()));
+                stack.push((*addr1, actions));
+            }
+            Instruction::Save(n) => {
+                actions.push(Action::Save(*n));
+                stack.push((pc + 1, actions));
+            }
+            Instruction::Progress(n) => {
+                // 同じ経路で繰り返しの開始位置を保存していれば、入力を消費していない
+                if !actions.contains(&Action::Save(*n)) {
+                    stack.push((pc + 1, actions));
+                }
+            }
+            Instruction::AssertStart
+            | Instruction::AssertEnd
+            | Instruction::AssertStartLine
+            | Instruction::AssertEndLine
+            | Instruction::WordBoundary { .. }
+            | Instruction::NotWordBoundary { .. } => {
+                actions.push(Action::Assert(pc));
+                stack.push((pc + 1, actions));
+            }
+            _ => return None,
+        }
+    }
+
+    // 入力を消費する命令が受理する文字が重なる場合は、one-passではない
+    let mut ranges: Vec<(char, char)> = consumers
+        .iter()
+        .flat_map(|(pc, _)| char_ranges(&inst[*pc]))
+        .collect();
+    ranges.sort_unstable();
+    if ranges.windows(2).any(|w| w[1].0 <= w[0].1) {
+        return None;
+    }
+
+    Some(State { consumers, matched })
+}
+
+/// startの状態から入力を消費するまでの、すべての経路が入力の先頭のアサーションを通るか判定する。
+fn is_anchored(inst: &[Instruction], start: &State) -> bool {
+    let anchored = |actions: &[Action]| {
+        actions
+            .iter()
+            .any(|action| matches!(action, Action::Assert(pc) if matches!(inst[*pc], Instruction::AssertStart)))
+    };
+    start
+        .consumers
+        .iter()
+        .map(|(_, actions)| actions)
+        .chain(start.matched.as_ref().map(|(actions, _)| actions))
+        .all(|actions| anchored(actions))
+}
+
+/// 入力を消費する命令が受理する文字の範囲を返す。
+fn char_ranges(inst: &Instruction) -> Vec<(char, char)> {
+    match inst {
+        Instruction::Char(c) => vec![(*c, *c)],
+        Instruction::Any => vec![('\0', char::MAX)],
+        Instruction::AnyNotNewline => vec![('\0', '\t'), ('\u{B}', char::MAX)],
+        Instruction::Class(class) => class.ranges().to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+/// 入力を消費する命令がcを受理するか判定する。
+fn accepts(inst: &Instruction, c: char) -> bool {
+    match inst {
+        Instruction::Char(ch) => *ch == c,
+        Instruction::Any => true,
+        Instruction::AnyNotNewline => c != '\n',
+        Instruction::Class(class) => class.contains(c),
+        _ => false,
+    }
+}
+
+/// 経路上のアサーション命令が、lineのsp文字目の位置ですべて条件を満たすか判定する。
+fn is_asserted(inst: &[Instruction], line: &[char], sp: usize, actions: &[Action]) -> bool {
+    actions.iter().all(|action| match action {
+        Action::Assert(pc) => evaluator::is_asserted(&inst[*pc], line, sp),
+        Action::Save(_) => true,
+    })
+}
+
+/// 経路上のsave命令を実行する。
+fn save(actions: &[Action], slots: &mut [Option<usize>], sp: usize) {
+    for action in actions {
+        if let Action::Save(n) = action
+            && let Some(slot) = slots.get_mut(*n)
+        {
+            *slot = Some(sp);
+        }
+    }
+}
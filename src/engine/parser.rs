@@ -1,40 +1,319 @@
 //! Parses regular expression expressions and converts them to AST.
+use super::class::CharClass;
 use std::{
+    collections::HashMap,
     error::Error,
     fmt::{self, Display},
+    iter::{Enumerate, Peekable},
     mem::take,
+    str::Chars,
 };
 
 /// Types for expressing AST.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub enum AST {
     Char(char),
-    Dot,
-    Plus(Box<AST>),
-    Star(Box<AST>),
-    Question(Box<AST>),
+    Dot(bool),            // The bool is true if . matches \n as well.
+    Plus(Box<AST>, bool), // The bool is true if greedy, false if lazy.
+    Star(Box<AST>, bool),
+    Question(Box<AST>, bool),
     Or(Box<AST>, Box<AST>),
     Seq(Vec<AST>),
+    Capture(usize, Option<String>, Box<AST>), // Group number, name and expression.
+    Class(CharClass),
+    Assert(Assertion),
+    Repeat {
+        ast: Box<AST>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+    Backref {
+        group: usize,
+        case_insensitive: bool,
+        ascii: bool, // If true, only ASCII letters are compared case-insensitively.
+    },
+    Look(Look, Box<AST>),
+    Atomic(Box<AST>), // Once the expression matches, the alternatives inside it are discarded.
+}
+
+/// Lookaround assertions that match a position if the body matches right after or before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Look {
+    /// (?=...), or (?!...) if negated.
+    Ahead { negated: bool },
+    /// (?<=...), or (?<!...) if negated. The body matches between min and max characters.
+    Behind {
+        negated: bool,
+        min: usize,
+        max: usize,
+    },
+}
+
+/// Zero-width assertions that match a position without consuming input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assertion {
+    StartText,                       // \A, or ^ without the multi-line flag
+    EndText,                         // \z, or $ without the multi-line flag
+    StartLine,                       // ^ with the multi-line flag
+    EndLine,                         // $ with the multi-line flag
+    WordBoundary { ascii: bool },    // \b
+    NotWordBoundary { ascii: bool }, // \B
+}
+
+/// Options that change how an expression is parsed.
+#[derive(Debug, Clone, Copy)]
+pub struct Flags {
+    /// If true, ^ and $ match at the start and end of each line.
+    pub multi_line: bool,
+    /// If true, word characters are defined by Unicode, otherwise only by ASCII.
+    pub unicode: bool,
+    /// If true, letters match regardless of case.
+    pub case_insensitive: bool,
+    /// If true, . matches any character including \n.
+    pub dot_matches_new_line: bool,
+    /// If true, whitespace and comments starting with # are ignored outside brackets.
+    pub verbose: bool,
+}
+
+impl Default for Flags {
+    fn default() -> Self {
+        Flags {
+            multi_line: false,
+            unicode: true,
+            case_insensitive: false,
+            dot_matches_new_line: false,
+            verbose: false,
+        }
+    }
+}
+
+impl Flags {
+    /// Adds the case variants of the characters to the class if case-insensitive.
+    ///
+    /// Without the Unicode flag, only ASCII letters are folded.
+    fn fold(&self, class: &mut CharClass) {
+        if self.case_insensitive {
+            class.case_fold(!self.unicode);
+        }
+    }
+
+    /// Converts a literal character to AST, matching its case variants if case-insensitive.
+    fn literal(&self, c: char) -> AST {
+        let mut class = CharClass::new(vec![(c, c)]);
+        self.fold(&mut class);
+        if class == CharClass::new(vec![(c, c)]) {
+            AST::Char(c)
+        } else {
+            AST::Class(class)
+        }
+    }
 }
 
 impl AST {
+    /// Returns true if the expression can match the empty string.
+    pub fn is_nullable(&self) -> bool {
+        match self {
+            AST::Char(_) | AST::Dot(_) | AST::Class(_) => false,
+            AST::Plus(ast, _) | AST::Capture(_, _, ast) | AST::Atomic(ast) => ast.is_nullable(),
+            AST::Star(..)
+            | AST::Question(..)
+            | AST::Assert(_)
+            | AST::Backref { .. }
+            | AST::Look(..) => true,
+            AST::Repeat { ast, min, .. } => *min == 0 || ast.is_nullable(),
+            AST::Or(lhs, rhs) => lhs.is_nullable() || rhs.is_nullable(),
+            AST::Seq(nodes) => nodes.iter().all(|node| node.is_nullable()),
+        }
+    }
+
+    /// Returns the largest capture group number in the expression, or 0 if there is none.
+    pub fn max_capture(&self) -> usize {
+        match self {
+            AST::Char(_) | AST::Dot(_) | AST::Class(_) | AST::Assert(_) | AST::Backref { .. } => 0,
+            AST::Plus(ast, _)
+            | AST::Star(ast, _)
+            | AST::Question(ast, _)
+            | AST::Repeat { ast, .. }
+            | AST::Look(_, ast)
+            | AST::Atomic(ast) => ast.max_capture(),
+            AST::Capture(n, _, ast) => (*n).max(ast.max_capture()),
+            AST::Or(lhs, rhs) => lhs.max_capture().max(rhs.max_capture()),
+            AST::Seq(nodes) => nodes
+                .iter()
+                .map(|node| node.max_capture())
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    /// Returns the minimum and maximum numbers of characters the expression can match.
+    ///
+    /// The maximum is None if it is unbounded, such as for `a*` or a backreference.
+    pub fn length(&self) -> (usize, Option<usize>) {
+        match self {
+            AST::Char(_) | AST::Dot(_) | AST::Class(_) => (1, Some(1)),
+            AST::Assert(_) | AST::Look(..) => (0, Some(0)),
+            AST::Backref { .. } => (0, None),
+            AST::Capture(_, _, ast) | AST::Atomic(ast) => ast.length(),
+            AST::Plus(ast, _) => match ast.length() {
+                (min, Some(0)) => (min, Some(0)),
+                (min, _) => (min, None),
+            },
+            AST::Star(ast, _) => match ast.length() {
+                (_, Some(0)) => (0, Some(0)),
+                _ => (0, None),
+            },
+            AST::Question(ast, _) => (0, ast.length().1),
+            AST::Repeat { ast, min, max, .. } => {
+                let (lo, hi) = ast.length();
+                let max = match (hi, max) {
+                    (Some(0), _) => Some(0),
+                    (Some(hi), Some(max)) => hi.checked_mul(*max as usize),
+                    _ => None,
+                };
+                (lo.saturating_mul(*min as usize), max)
+            }
+            AST::Or(lhs, rhs) => {
+                let (lhs_min, lhs_max) = lhs.length();
+                let (rhs_min, rhs_max) = rhs.length();
+                let max = lhs_max.zip(rhs_max).map(|(l, r)| l.max(r));
+                (lhs_min.min(rhs_min), max)
+            }
+            AST::Seq(nodes) => nodes.iter().fold((0, Some(0)), |(min, max), node| {
+                let (lo, hi) = node.length();
+                let max = max.zip(hi).and_then(|(max, hi)| max.checked_add(hi));
+                (min.saturating_add(lo), max)
+            }),
+        }
+    }
+
+    /// Returns the expression matching the reversed strings of those the expression matches.
+    ///
+    /// Start and end assertions are swapped, so the result can be matched against the reversed input.
+    /// Returns None if the expression contains a backreference, a lookaround or an atomic group,
+    /// which cannot be reversed.
+    pub fn reversed(&self) -> Option<AST> {
+        let ast = match self {
+            AST::Char(c) => AST::Char(*c),
+            AST::Dot(matches_new_line) => AST::Dot(*matches_new_line),
+            AST::Class(class) => AST::Class(class.clone()),
+            AST::Assert(assertion) => AST::Assert(match assertion {
+                Assertion::StartText => Assertion::EndText,
+                Assertion::EndText => Assertion::StartText,
+                Assertion::StartLine => Assertion::EndLine,
+                Assertion::EndLine => Assertion::StartLine,
+                assertion => *assertion,
+            }),
+            AST::Backref { .. } | AST::Look(..) | AST::Atomic(_) => return None,
+            AST::Plus(ast, greedy) => AST::Plus(Box::new(ast.reversed()?), *greedy),
+            AST::Star(ast, greedy) => AST::Star(Box::new(ast.reversed()?), *greedy),
+            AST::Question(ast, greedy) => AST::Question(Box::new(ast.reversed()?), *greedy),
+            AST::Repeat {
+                ast,
+                min,
+                max,
+                greedy,
+            } => AST::Repeat {
+                ast: Box::new(ast.reversed()?),
+                min: *min,
+                max: *max,
+                greedy: *greedy,
+            },
+            AST::Capture(n, name, ast) => AST::Capture(*n, name.clone(), Box::new(ast.reversed()?)),
+            AST::Or(lhs, rhs) => AST::Or(Box::new(lhs.reversed()?), Box::new(rhs.reversed()?)),
+            AST::Seq(nodes) => AST::Seq(
+                nodes
+                    .iter()
+                    .rev()
+                    .map(|node| node.reversed())
+                    .collect::<Option<_>>()?,
+            ),
+        };
+        Some(ast)
+    }
+
+    /// Returns the numbers of the named capture groups in the expression, keyed by name.
+    pub fn capture_names(&self) -> HashMap<String, usize> {
+        fn collect(ast: &AST, names: &mut HashMap<String, usize>) {
+            match ast {
+                AST::Char(_)
+                | AST::Dot(_)
+                | AST::Class(_)
+                | AST::Assert(_)
+                | AST::Backref { .. } => (),
+                AST::Plus(ast, _)
+                | AST::Star(ast, _)
+                | AST::Question(ast, _)
+                | AST::Repeat { ast, .. }
+                | AST::Look(_, ast)
+                | AST::Atomic(ast) => collect(ast, names),
+                AST::Capture(n, name, ast) => {
+                    if let Some(name) = name {
+                        names.insert(name.clone(), *n);
+                    }
+                    collect(ast, names);
+                }
+                AST::Or(lhs, rhs) => {
+                    collect(lhs, names);
+                    collect(rhs, names);
+                }
+                AST::Seq(nodes) => nodes.iter().for_each(|node| collect(node, names)),
+            }
+        }
+
+        let mut names = HashMap::new();
+        collect(self, &mut names);
+        names
+    }
+
     fn fmt_with_indent(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
         let indent = " ".repeat(depth);
         let branch = if depth == 0 { "  " } else { "└─" };
 
         match self {
             AST::Char(c) => writeln!(f, "{}└─Char({})", indent, c),
-            AST::Dot => writeln!(f, "{}└─Dot", indent),
-            AST::Plus(ast) => {
-                writeln!(f, "{}{}Plus", indent, branch)?;
+            AST::Dot(false) => writeln!(f, "{}└─Dot", indent),
+            AST::Dot(true) => writeln!(f, "{}└─Dot(s)", indent),
+            AST::Class(class) => writeln!(f, "{}└─Class({})", indent, class),
+            AST::Assert(assertion) => writeln!(f, "{}└─Assert({:?})", indent, assertion),
+            AST::Backref {
+                group,
+                case_insensitive,
+                ..
+            } => {
+                let suffix = if *case_insensitive { "(i)" } else { "" };
+                writeln!(f, "{}└─Backref({}){}", indent, group, suffix)
+            }
+            AST::Plus(ast, greedy) => {
+                writeln!(f, "{}{}Plus{}", indent, branch, lazy_suffix(*greedy))?;
                 ast.fmt_with_indent(f, depth + 2)
             }
-            AST::Star(ast) => {
-                writeln!(f, "{}{}Star", indent, branch)?;
+            AST::Star(ast, greedy) => {
+                writeln!(f, "{}{}Star{}", indent, branch, lazy_suffix(*greedy))?;
                 ast.fmt_with_indent(f, depth + 2)
             }
-            AST::Question(ast) => {
-                writeln!(f, "{}{}Question", indent, branch)?;
+            AST::Question(ast, greedy) => {
+                writeln!(f, "{}{}Question{}", indent, branch, lazy_suffix(*greedy))?;
+                ast.fmt_with_indent(f, depth + 2)
+            }
+            AST::Repeat {
+                ast,
+                min,
+                max,
+                greedy,
+            } => {
+                let lazy = lazy_suffix(*greedy);
+                match max {
+                    Some(max) if max == min => {
+                        writeln!(f, "{}{}Repeat{{{}}}{}", indent, branch, min, lazy)?
+                    }
+                    Some(max) => {
+                        writeln!(f, "{}{}Repeat{{{},{}}}{}", indent, branch, min, max, lazy)?
+                    }
+                    None => writeln!(f, "{}{}Repeat{{{},}}{}", indent, branch, min, lazy)?,
+                }
                 ast.fmt_with_indent(f, depth + 2)
             }
             AST::Or(lhs, rhs) => {
@@ -49,10 +328,30 @@ impl AST {
                 }
                 Ok(())
             }
+            AST::Look(look, ast) => {
+                writeln!(f, "{}{}{:?}", indent, branch, look)?;
+                ast.fmt_with_indent(f, depth + 2)
+            }
+            AST::Atomic(ast) => {
+                writeln!(f, "{}{}Atomic", indent, branch)?;
+                ast.fmt_with_indent(f, depth + 2)
+            }
+            AST::Capture(n, name, ast) => {
+                match name {
+                    Some(name) => writeln!(f, "{}{}Capture({}, {})", indent, branch, n, name)?,
+                    None => writeln!(f, "{}{}Capture({})", indent, branch, n)?,
+                }
+                ast.fmt_with_indent(f, depth + 2)
+            }
         }
     }
 }
 
+/// Suffix for displaying lazy quantifiers.
+fn lazy_suffix(greedy: bool) -> &'static str {
+    if greedy { "" } else { "(lazy)" }
+}
+
 impl Display for AST {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.fmt_with_indent(f, 0)
@@ -66,6 +365,21 @@ pub enum ParseError {
     InvalidRightParen(usize),
     NoPrev(usize),
     NoRightParen,
+    NoRightBracket(usize),
+    InvalidRange(usize, char, char),
+    InvalidRepeat(usize),
+    InvalidHexDigit(usize, char),
+    IncompleteHex(usize),
+    InvalidCodePoint(usize, String),
+    InvalidFlag(usize, char),
+    InvalidGroupName(usize),
+    DuplicateGroupName(usize, String),
+    InvalidBackref(usize),
+    UnboundedLookbehind(usize),
+    UnknownGroupName(usize, String),
+    IncompleteProperty(usize),
+    UnknownProperty(usize, String),
+    PropertyUnavailable(usize),
     Empty,
 }
 
@@ -78,12 +392,85 @@ impl Display for ParseError {
             ParseError::InvalidRightParen(pos) => {
                 write!(f, "ParseError: invalid right parenthesis: pos = {}", pos)
             }
+            ParseError::InvalidRepeat(pos) => {
+                write!(f, "ParseError: invalid repetition: pos = {}", pos)
+            }
+            ParseError::InvalidHexDigit(pos, c) => {
+                write!(
+                    f,
+                    "ParseError: invalid hex digit: pos = {}, char = {}",
+                    pos, c
+                )
+            }
+            ParseError::IncompleteHex(pos) => {
+                write!(f, "ParseError: incomplete hex escape: pos = {}", pos)
+            }
+            ParseError::InvalidCodePoint(pos, hex) => {
+                write!(
+                    f,
+                    "ParseError: invalid code point: pos = {}, code point = U+{}",
+                    pos, hex
+                )
+            }
+            ParseError::InvalidFlag(pos, c) => {
+                write!(f, "ParseError: invalid flag: pos = {}, char = {}", pos, c)
+            }
+            ParseError::InvalidGroupName(pos) => {
+                write!(f, "ParseError: invalid group name: pos = {}", pos)
+            }
+            ParseError::DuplicateGroupName(pos, name) => {
+                write!(
+                    f,
+                    "ParseError: duplicate group name: pos = {}, name = {}",
+                    pos, name
+                )
+            }
+            ParseError::InvalidBackref(pos) => {
+                write!(f, "ParseError: invalid backreference: pos = {}", pos)
+            }
+            ParseError::UnboundedLookbehind(pos) => {
+                write!(f, "ParseError: unbounded lookbehind: pos = {}", pos)
+            }
+            ParseError::UnknownGroupName(pos, name) => {
+                write!(
+                    f,
+                    "ParseError: unknown group name: pos = {}, name = {}",
+                    pos, name
+                )
+            }
+            ParseError::IncompleteProperty(pos) => {
+                write!(f, "ParseError: incomplete property name: pos = {}", pos)
+            }
+            ParseError::UnknownProperty(pos, name) => {
+                write!(
+                    f,
+                    "ParseError: unknown property: pos = {}, name = {}",
+                    pos, name
+                )
+            }
+            ParseError::PropertyUnavailable(pos) => {
+                write!(
+                    f,
+                    "ParseError: Unicode property tables are disabled (enable the unicode-tables feature): pos = {}",
+                    pos
+                )
+            }
             ParseError::NoPrev(pos) => {
                 write!(f, "ParseError: no previous expression: pos = {}", pos)
             }
             ParseError::NoRightParen => {
                 write!(f, "ParseError: no right parenthesis")
             }
+            ParseError::NoRightBracket(pos) => {
+                write!(f, "ParseError: no right bracket: pos = {}", pos)
+            }
+            ParseError::InvalidRange(pos, start, end) => {
+                write!(
+                    f,
+                    "ParseError: invalid range: pos = {}, range = {}-{}",
+                    pos, start, end
+                )
+            }
             ParseError::Empty => write!(f, "ParseError: empty expression"),
         }
     }
@@ -91,10 +478,40 @@ impl Display for ParseError {
 
 impl Error for ParseError {}
 
+/// Iterator over the characters of an expression and their positions.
+type ExprChars<'a> = Peekable<Enumerate<Chars<'a>>>;
+
 /// Escaping special characters
-fn parse_escape(pos: usize, c: char) -> Result<AST, ParseError> {
+///
+/// `pos` is the position of c, the character following the backslash.
+/// Hex escapes such as `\x41` or `\u{1F600}` read their digits from chars.
+fn parse_escape(
+    chars: &mut ExprChars,
+    pos: usize,
+    c: char,
+    flags: &Flags,
+) -> Result<AST, ParseError> {
+    let ascii = !flags.unicode;
     match c {
-        '\\' | '(' | ')' | '|' | '.' | '+' | '*' | '?' => Ok(AST::Char(c)),
+        'a' => Ok(AST::Char('\x07')),
+        'e' => Ok(AST::Char('\x1B')),
+        'f' => Ok(AST::Char('\x0C')),
+        'n' => Ok(AST::Char('\n')),
+        'r' => Ok(AST::Char('\r')),
+        't' => Ok(AST::Char('\t')),
+        'v' => Ok(AST::Char('\x0B')),
+        '0' => Ok(AST::Char('\0')),
+        'x' => Ok(AST::Char(parse_hex(chars, pos, 2)?)),
+        'u' => Ok(AST::Char(parse_hex(chars, pos, 4)?)),
+        'U' => Ok(AST::Char(parse_hex(chars, pos, 8)?)),
+        'A' => Ok(AST::Assert(Assertion::StartText)),
+        'z' => Ok(AST::Assert(Assertion::EndText)),
+        'b' => Ok(AST::Assert(Assertion::WordBoundary { ascii })),
+        'B' => Ok(AST::Assert(Assertion::NotWordBoundary { ascii })),
+        'd' | 'D' | 'w' | 'W' | 's' | 'S' => Ok(AST::Class(perl_class(c, flags))),
+        'p' | 'P' => Ok(AST::Class(parse_property(chars, pos, c == 'P', flags)?)),
+        '^' | '$' | '#' | ' ' => Ok(AST::Char(c)),
+        '\\' | '(' | ')' | '|' | '.' | '+' | '*' | '?' | '[' | ']' | '{' | '}' => Ok(AST::Char(c)),
         _ => {
             let err = ParseError::InvalidEscape(pos, c);
             Err(err)
@@ -102,36 +519,413 @@ fn parse_escape(pos: usize, c: char) -> Result<AST, ParseError> {
     }
 }
 
+/// Parses the digits of a hex escape and converts them to a char.
+///
+/// The digits are either exactly `len` hex digits, such as `\x41`, `\u00E9` and `\U0001F600`,
+/// or any number of hex digits in braces, such as `\x{41}` or `\u{1F600}`.
+/// It is an error if the digits do not form a Unicode scalar value.
+fn parse_hex(chars: &mut ExprChars, pos: usize, len: usize) -> Result<char, ParseError> {
+    let braced = chars.next_if(|(_, c)| *c == '{').is_some();
+    let mut digits = String::new();
+    while braced || digits.len() < len {
+        match chars.next() {
+            Some((_, '}')) if braced && !digits.is_empty() => break,
+            Some((_, c)) if c.is_ascii_hexdigit() => digits.push(c),
+            Some((i, c)) => return Err(ParseError::InvalidHexDigit(i, c)),
+            None => return Err(ParseError::IncompleteHex(pos)),
+        }
+    }
+    u32::from_str_radix(&digits, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or(ParseError::InvalidCodePoint(pos, digits))
+}
+
+/// Parses the name of a Unicode property class and converts it to a class.
+///
+/// The name is either a single letter, such as `\pL`, or enclosed in braces, such as `\p{Greek}`.
+/// If negated is true, as for `\P{Greek}`, the class is negated.
+fn parse_property(
+    chars: &mut ExprChars,
+    pos: usize,
+    negated: bool,
+    flags: &Flags,
+) -> Result<CharClass, ParseError> {
+    let name = match chars.next() {
+        Some((_, '{')) => {
+            let mut name = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '}')) if !name.is_empty() => break,
+                    Some((_, '}')) | None => return Err(ParseError::IncompleteProperty(pos)),
+                    Some((_, c)) => name.push(c),
+                }
+            }
+            name
+        }
+        Some((_, c)) => c.to_string(),
+        None => return Err(ParseError::IncompleteProperty(pos)),
+    };
+
+    let err = if cfg!(feature = "unicode-tables") {
+        ParseError::UnknownProperty(pos, name.clone())
+    } else {
+        ParseError::PropertyUnavailable(pos)
+    };
+    let mut class = CharClass::property(&name).ok_or(err)?;
+    flags.fold(&mut class);
+    if negated {
+        class.negate();
+    }
+    Ok(class)
+}
+
+/// Converts a Perl shorthand class such as `\d`, `\w`, `\s` or their negations `\D`, `\W`, `\S`.
+fn perl_class(c: char, flags: &Flags) -> CharClass {
+    let ascii = !flags.unicode;
+    let mut class = match c.to_ascii_lowercase() {
+        'd' => CharClass::digit(ascii),
+        'w' => CharClass::word(ascii),
+        _ => CharClass::space(ascii),
+    };
+    flags.fold(&mut class);
+    if c.is_ascii_uppercase() {
+        class.negate();
+    }
+    class
+}
+
+/// An escape sequence inside brackets.
+enum ClassEscape {
+    Char(char),
+    Class(CharClass), // A shorthand class such as \d.
+}
+
+/// Escaping special characters inside brackets.
+///
+/// In addition to the characters escapable outside brackets, `-` can be escaped.
+fn parse_class_escape(
+    chars: &mut ExprChars,
+    pos: usize,
+    flags: &Flags,
+) -> Result<ClassEscape, ParseError> {
+    match chars.next() {
+        Some((_, '-')) => Ok(ClassEscape::Char('-')),
+        Some((i, c)) => match parse_escape(chars, i, c, flags)? {
+            AST::Char(c) => Ok(ClassEscape::Char(c)),
+            AST::Class(class) => Ok(ClassEscape::Class(class)),
+            _ => Err(ParseError::InvalidEscape(i, c)),
+        },
+        None => Err(ParseError::NoRightBracket(pos)),
+    }
+}
+
+/// Converts a bracket expression such as `[a-z_]` or `[^0-9]` to AST::Class.
+///
+/// `pos` is the position of the opening bracket, which has already been consumed.
+/// A `]` right after `[` or `[^` and a `-` at the start or end of the brackets are literals.
+/// Shorthand classes such as `\d` may appear inside brackets, and a `-` next to them is a literal.
+fn parse_class(chars: &mut ExprChars, pos: usize, flags: &Flags) -> Result<AST, ParseError> {
+    let negated = chars.next_if(|(_, c)| *c == '^').is_some();
+    let mut ranges = Vec::new();
+    let mut class = CharClass::default();
+    let mut first = true;
+
+    loop {
+        let (i, c) = chars.next().ok_or(ParseError::NoRightBracket(pos))?;
+        let start = match c {
+            ']' if !first => break,
+            '\\' => match parse_class_escape(chars, pos, flags)? {
+                ClassEscape::Char(c) => c,
+                ClassEscape::Class(shorthand) => {
+                    class.union(&shorthand);
+                    first = false;
+                    continue;
+                }
+            },
+            _ => c,
+        };
+        first = false;
+
+        // A “-” followed by “]” is a literal, such as “[a-]”.
+        if chars.next_if(|(_, c)| *c == '-').is_none() {
+            ranges.push((start, start));
+            continue;
+        }
+        let end = match chars.next().ok_or(ParseError::NoRightBracket(pos))? {
+            (_, ']') => {
+                ranges.push((start, start));
+                ranges.push(('-', '-'));
+                break;
+            }
+            (_, '\\') => match parse_class_escape(chars, pos, flags)? {
+                ClassEscape::Char(c) => c,
+                // A range cannot end with a shorthand class, so “[a-\d]” is a, - and \d.
+                ClassEscape::Class(shorthand) => {
+                    ranges.push((start, start));
+                    ranges.push(('-', '-'));
+                    class.union(&shorthand);
+                    continue;
+                }
+            },
+            (_, c) => c,
+        };
+        if start > end {
+            return Err(ParseError::InvalidRange(i, start, end));
+        }
+        ranges.push((start, end));
+    }
+
+    class.union(&CharClass::new(ranges));
+    flags.fold(&mut class);
+    if negated {
+        class.negate();
+    }
+    Ok(AST::Class(class))
+}
+
 /// Enumerated type for use in the parse_dot_plus_star_question function
+#[allow(clippy::upper_case_acronyms)]
 enum PSQ {
     Plus,
     Star,
     Question,
+    Repeat(u32, Option<u32>),
 }
 
-/// ., +, *, ? to AST.
+/// ., +, *, ?, {n,m} to AST.
 ///
-/// In postfix notation, it is an error if there is no pattern before ., +, *, or ?.
+/// In postfix notation, it is an error if there is no pattern before ., +, *, ?, or {n,m}.
+/// A trailing ? makes the quantifier lazy, such as *?, +?, ??, {n,m}?.
+/// A trailing + makes the quantifier possessive, such as *+, ++, ?+, {n,m}+,
+/// which is the same as an atomic group, such as (?>a*) for a*+.
 ///
-/// Example: *ab, abc|+, etc. are errors.
+/// Example: *ab, abc|+, {2}a, etc. are errors.
 fn parse_dot_plus_star_question(
     seq: &mut Vec<AST>,
+    chars: &mut ExprChars,
     ast_type: PSQ,
     pos: usize,
 ) -> Result<(), ParseError> {
+    let greedy = chars.next_if(|(_, c)| *c == '?').is_none();
+    let possessive = greedy && chars.next_if(|(_, c)| *c == '+').is_some();
     if let Some(prev) = seq.pop() {
         let ast = match ast_type {
-            PSQ::Plus => AST::Plus(Box::new(prev)),
-            PSQ::Star => AST::Star(Box::new(prev)),
-            PSQ::Question => AST::Question(Box::new(prev)),
+            PSQ::Plus => AST::Plus(Box::new(prev), greedy),
+            PSQ::Star => AST::Star(Box::new(prev), greedy),
+            PSQ::Question => AST::Question(Box::new(prev), greedy),
+            PSQ::Repeat(min, max) => AST::Repeat {
+                ast: Box::new(prev),
+                min,
+                max,
+                greedy,
+            },
         };
-        seq.push(ast);
+        if possessive {
+            seq.push(AST::Atomic(Box::new(ast)));
+        } else {
+            seq.push(ast);
+        }
         Ok(())
     } else {
         Err(ParseError::NoPrev(pos))
     }
 }
 
+/// Parses the bounds of a counted repetition: `{n}`, `{n,}` or `{n,m}`.
+///
+/// `pos` is the position of the opening brace, which has already been consumed.
+/// Returns None without consuming anything if the brace does not start well-formed bounds,
+/// in which case it is a literal `{`, such as in `a{` or `{foo}`.
+/// It is an error if a bound is too large or n is greater than m.
+fn parse_repeat(chars: &mut ExprChars, pos: usize) -> Result<Option<PSQ>, ParseError> {
+    fn parse_number(chars: &mut ExprChars, pos: usize) -> Result<Option<u32>, ParseError> {
+        let mut digits = String::new();
+        while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+            digits.push(c);
+        }
+        if digits.is_empty() {
+            Ok(None)
+        } else {
+            let n = digits.parse().map_err(|_| ParseError::InvalidRepeat(pos))?;
+            Ok(Some(n))
+        }
+    }
+
+    // Reads ahead on a copy, so that a literal brace leaves chars untouched.
+    let mut ahead = chars.clone();
+    let Some(min) = parse_number(&mut ahead, pos)? else {
+        return Ok(None);
+    };
+    let max = match ahead.next() {
+        Some((_, '}')) => Some(min),
+        Some((_, ',')) => {
+            let max = parse_number(&mut ahead, pos)?;
+            if ahead.next_if(|(_, c)| *c == '}').is_none() {
+                return Ok(None);
+            }
+            max
+        }
+        _ => return Ok(None),
+    };
+    if max.is_some_and(|max| min > max) {
+        return Err(ParseError::InvalidRepeat(pos));
+    }
+    *chars = ahead;
+    Ok(Some(PSQ::Repeat(min, max)))
+}
+
+/// Parses inline flags such as `(?i)`, `(?m-s)` or `(?x:` and sets them.
+///
+/// `(?` has already been consumed.
+/// The flags are i (case-insensitive), m (multi-line), s (dot matches \n) and x (verbose),
+/// and those after `-` are cleared.
+/// Returns true if the flags are followed by `:` and only apply to the group, such as `(?i:abc)`,
+/// or false if followed by `)` and apply to the rest of the current group, such as `(?i)abc`.
+/// `(?:` has no flags and only groups the expression without capturing.
+fn parse_flags(chars: &mut ExprChars, flags: &mut Flags) -> Result<bool, ParseError> {
+    let mut empty = true;
+    let mut negated = false;
+    loop {
+        let (i, c) = chars.next().ok_or(ParseError::NoRightParen)?;
+        let flag = match c {
+            'i' => &mut flags.case_insensitive,
+            'm' => &mut flags.multi_line,
+            's' => &mut flags.dot_matches_new_line,
+            'x' => &mut flags.verbose,
+            '-' if !negated => {
+                negated = true;
+                empty = true;
+                continue;
+            }
+            ':' if !negated || !empty => return Ok(true),
+            ')' if !empty => return Ok(false),
+            _ => return Err(ParseError::InvalidFlag(i, c)),
+        };
+        *flag = !negated;
+        empty = false;
+    }
+}
+
+/// Kinds of groups opened by a left parenthesis.
+enum Group {
+    Capture(usize, Option<String>), // Group number and name.
+    NonCapturing,
+    Atomic,
+    LookAhead { negated: bool },
+    LookBehind { negated: bool, pos: usize }, // pos is the position of the opening parenthesis.
+}
+
+/// Parses the kind of a group starting with `(?`, such as `(?:`, `(?P<name>`, `(?<=` or `(?>`.
+///
+/// `pos` is the position of the opening parenthesis, and `(?` has already been consumed.
+/// Returns None for inline flags such as `(?i)`, which set flags for the rest of the current group.
+fn parse_group_kind(
+    chars: &mut ExprChars,
+    pos: usize,
+    flags: &mut Flags,
+) -> Result<Option<Group>, ParseError> {
+    let next = chars.peek().map(|(_, c)| *c);
+    let group = match next {
+        Some(c @ ('=' | '!')) => {
+            chars.next();
+            Group::LookAhead { negated: c == '!' }
+        }
+        Some('<') => {
+            chars.next();
+            match chars.next_if(|(_, c)| *c == '=' || *c == '!') {
+                Some((_, c)) => Group::LookBehind {
+                    negated: c == '!',
+                    pos,
+                },
+                None => Group::Capture(0, Some(parse_group_name(chars, pos)?)),
+            }
+        }
+        Some('>') => {
+            chars.next();
+            Group::Atomic
+        }
+        Some('P') => {
+            chars.next();
+            if chars.next_if(|(_, c)| *c == '<').is_none() {
+                return Err(ParseError::InvalidGroupName(pos));
+            }
+            Group::Capture(0, Some(parse_group_name(chars, pos)?))
+        }
+        _ if parse_flags(chars, flags)? => Group::NonCapturing,
+        _ => return Ok(None),
+    };
+    Ok(Some(group))
+}
+
+/// Parses the name of a named capture group such as `(?P<name>` or `(?<name>`.
+///
+/// `pos` is the position of the opening parenthesis, and `(?P<` or `(?<` has already been consumed.
+/// A name consists of alphanumerics and `_`, and must not start with a digit.
+fn parse_group_name(chars: &mut ExprChars, pos: usize) -> Result<String, ParseError> {
+    let mut name = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '>')) if !name.is_empty() => return Ok(name),
+            Some((_, c)) if c == '_' || c.is_alphanumeric() => {
+                if name.is_empty() && c.is_numeric() {
+                    return Err(ParseError::InvalidGroupName(pos));
+                }
+                name.push(c);
+            }
+            Some(_) => return Err(ParseError::InvalidGroupName(pos)),
+            None => return Err(ParseError::NoRightParen),
+        }
+    }
+}
+
+/// Converts a backreference such as `\1` or `\k<name>` to AST.
+///
+/// `pos` is the position of c, the character following the backslash.
+/// A backreference must refer to a group that has already been opened.
+/// `group` is the number of the last opened capture group and names maps group names to numbers.
+fn parse_backref(
+    chars: &mut ExprChars,
+    pos: usize,
+    c: char,
+    group: usize,
+    names: &HashMap<String, usize>,
+    flags: &Flags,
+) -> Result<AST, ParseError> {
+    let n = if c == 'k' {
+        if chars.next_if(|(_, c)| *c == '<').is_none() {
+            return Err(ParseError::InvalidBackref(pos));
+        }
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                Some((_, '>')) => break,
+                Some((_, c)) => name.push(c),
+                None => return Err(ParseError::InvalidBackref(pos)),
+            }
+        }
+        match names.get(&name) {
+            Some(n) => *n,
+            None => return Err(ParseError::UnknownGroupName(pos, name)),
+        }
+    } else {
+        let mut digits = c.to_string();
+        while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+            digits.push(c);
+        }
+        match digits.parse() {
+            Ok(n) if n <= group => n,
+            _ => return Err(ParseError::InvalidBackref(pos)),
+        }
+    };
+
+    Ok(AST::Backref {
+        group: n,
+        case_insensitive: flags.case_insensitive,
+        ascii: !flags.unicode,
+    })
+}
+
 /// Converts multiple expressions combined in Or to AST.
 ///
 /// For example, the abc|def|ghi would be the AST::Or(“abc”, AST::Or(“def”, “ghi”))).
@@ -151,7 +945,7 @@ fn fold_or(mut seq_or: Vec<AST>) -> Option<AST> {
 }
 
 /// Converts a regular expression to an abstract syntax tree.
-pub fn parse(expr: &str) -> Result<AST, ParseError> {
+pub fn parse(expr: &str, mut flags: Flags) -> Result<AST, ParseError> {
     // Types for representing internal states.
     // Char state: String processing in progress
     // Escape state: Escape sequence is being processed
@@ -164,36 +958,84 @@ pub fn parse(expr: &str) -> Result<AST, ParseError> {
     let mut seq_or = Vec::new();
     let mut stack = Vec::new();
     let mut state = ParseState::Char;
+    let mut group = 0; // Number of the last opened capture group.
+    let mut names = HashMap::new(); // Numbers of the named capture groups, keyed by name.
 
-    for (i, c) in expr.chars().enumerate() {
+    let mut chars = expr.chars().enumerate().peekable();
+    while let Some((i, c)) = chars.next() {
         match &state {
             ParseState::Char => match c {
-                '+' => parse_dot_plus_star_question(&mut seq, PSQ::Plus, i)?,
-                '*' => parse_dot_plus_star_question(&mut seq, PSQ::Star, i)?,
-                '?' => parse_dot_plus_star_question(&mut seq, PSQ::Question, i)?,
+                '+' => parse_dot_plus_star_question(&mut seq, &mut chars, PSQ::Plus, i)?,
+                '*' => parse_dot_plus_star_question(&mut seq, &mut chars, PSQ::Star, i)?,
+                '?' => parse_dot_plus_star_question(&mut seq, &mut chars, PSQ::Question, i)?,
+                '{' => match parse_repeat(&mut chars, i)? {
+                    Some(repeat) => parse_dot_plus_star_question(&mut seq, &mut chars, repeat, i)?,
+                    None => seq.push(flags.literal(c)),
+                },
                 '(' => {
-                    // Stores the current context on the stack,
+                    let prev_flags = flags;
+                    let kind = if chars.next_if(|(_, c)| *c == '?').is_some() {
+                        match parse_group_kind(&mut chars, i, &mut flags)? {
+                            Some(kind) => kind,
+                            // Inline flags such as “(?i)” apply to the rest of the current group.
+                            None => continue,
+                        }
+                    } else {
+                        Group::Capture(0, None)
+                    };
+
+                    // Capture groups are numbered from 1 in the order of their opening parentheses.
+                    let kind = match kind {
+                        Group::Capture(_, name) => {
+                            group += 1;
+                            if let Some(name) = &name {
+                                if names.contains_key(name) {
+                                    return Err(ParseError::DuplicateGroupName(i, name.clone()));
+                                }
+                                names.insert(name.clone(), group);
+                            }
+                            Group::Capture(group, name)
+                        }
+                        kind => kind,
+                    };
+
+                    // Stores the current context and flags on the stack,
                     // Empty the current context.
                     let prev = take(&mut seq);
                     let prev_or = take(&mut seq_or);
-                    stack.push((prev, prev_or));
+                    stack.push((prev, prev_or, kind, prev_flags));
                 }
                 ')' => {
                     // Pop the current context off the stack.
-                    if let Some((mut prev, prev_or)) = stack.pop() {
+                    if let Some((mut prev, prev_or, kind, prev_flags)) = stack.pop() {
                         // Do not push if the expression is empty, such as “()”.
                         if !seq.is_empty() {
                             seq_or.push(AST::Seq(seq));
                         }
 
-                        // Generate Or.
-                        if let Some(ast) = fold_or(seq_or) {
-                            prev.push(ast);
-                        }
+                        // Generate Or and wrap it in a capture group or a lookaround unless non-capturing.
+                        // An empty group such as “()” captures the empty string.
+                        let ast = fold_or(seq_or).unwrap_or(AST::Seq(Vec::new()));
+                        let ast = match kind {
+                            Group::Capture(n, name) => AST::Capture(n, name, Box::new(ast)),
+                            Group::NonCapturing => ast,
+                            Group::Atomic => AST::Atomic(Box::new(ast)),
+                            Group::LookAhead { negated } => {
+                                AST::Look(Look::Ahead { negated }, Box::new(ast))
+                            }
+                            Group::LookBehind { negated, pos } => {
+                                // The body of a lookbehind must have a bounded length.
+                                let (min, max) = ast.length();
+                                let max = max.ok_or(ParseError::UnboundedLookbehind(pos))?;
+                                AST::Look(Look::Behind { negated, min, max }, Box::new(ast))
+                            }
+                        };
+                        prev.push(ast);
 
                         // Make the previous context the current context.
                         seq = prev;
                         seq_or = prev_or;
+                        flags = prev_flags;
                     } else {
                         // If there are no opening parentheses but closing parentheses, such as “abc)”, an error is returned.
                         return Err(ParseError::InvalidRightParen(i));
@@ -208,13 +1050,29 @@ pub fn parse(expr: &str) -> Result<AST, ParseError> {
                         seq_or.push(AST::Seq(prev));
                     }
                 }
+                c if flags.verbose && c.is_whitespace() => (),
+                '#' if flags.verbose => {
+                    // Skip a comment up to the end of the line.
+                    while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+                }
                 '\\' => state = ParseState::Escape,
-                '.' => seq.push(AST::Dot),
-                _ => seq.push(AST::Char(c)),
+                '.' => seq.push(AST::Dot(flags.dot_matches_new_line)),
+                '[' => seq.push(parse_class(&mut chars, i, &flags)?),
+                '^' if flags.multi_line => seq.push(AST::Assert(Assertion::StartLine)),
+                '^' => seq.push(AST::Assert(Assertion::StartText)),
+                '$' if flags.multi_line => seq.push(AST::Assert(Assertion::EndLine)),
+                '$' => seq.push(AST::Assert(Assertion::EndText)),
+                _ => seq.push(flags.literal(c)),
             },
             ParseState::Escape => {
                 // Escape sequence processing
-                let ast = parse_escape(i, c)?;
+                let ast = match c {
+                    '1'..='9' | 'k' => parse_backref(&mut chars, i, c, group, &names, &flags)?,
+                    _ => match parse_escape(&mut chars, i, c, &flags)? {
+                        AST::Char(c) => flags.literal(c),
+                        ast => ast,
+                    },
+                };
                 seq.push(ast);
                 state = ParseState::Char;
             }
@@ -0,0 +1,714 @@
+//! パースとコード生成を一度だけ行う、コンパイル済みの正規表現。
+use super::{
+    Engine, Instruction, codegen,
+    dfa::{self, Dfa},
+    evaluator,
+    lazy_dfa::{self, LazyDfa},
+    onepass::OnePass,
+    parser,
+};
+use crate::helper::DynError;
+use std::{collections::HashMap, sync::Arc};
+
+/// コンパイル済みの正規表現。
+///
+/// 生成時にパースとコード生成を一度だけ行い、ASTと命令列を保持する。
+/// マッチングのたびにパースやコード生成をやり直す必要がない。
+///
+/// 評価器を明示的に設定していない場合、キャプチャグループを含まない正規表現では、
+/// マッチしたかどうかの判定に遅延DFAを利用する。遅延DFAで評価できない場合は、デフォルトの評価器で評価する。
+/// また、one-passな正規表現では、逆順にした正規表現の遅延DFAで最左のマッチの開始位置を求め、
+/// そこからキャプチャグループの位置を入力の1回の走査で求める。
+/// [`RegexBuilder::dfa`]で、事前にDFAを構築することもできる。
+///
+/// # 利用例
+///
+/// ```
+/// use regex_engine::Regex;
+/// let re = Regex::new("abc|(de|cd)+").unwrap();
+/// assert!(re.is_match("xxdecd"));
+/// assert!(!re.is_match("xxx"));
+/// ```
+#[derive(Debug)]
+pub struct Regex {
+    expr: String,
+    ast: parser::AST,
+    code: Vec<Instruction>,
+    full_code: Vec<Instruction>,    // 入力全体とのマッチングを行う命令列
+    reverse_code: Vec<Instruction>, // 逆順にした入力とマッチングを行う命令列
+    engine: Engine,
+    names: Arc<HashMap<String, usize>>, // 名前付きキャプチャグループの名前と番号
+    dfa: Option<LazyDfa>,               // codeの遅延DFA
+    full_dfa: Option<LazyDfa>,          // full_codeの遅延DFA
+    reverse_dfa: Option<LazyDfa>,       // reverse_codeの遅延DFA
+    dense_dfa: Option<Dfa>,             // codeから事前に構築したDFA
+    onepass: Option<OnePass>,           // codeがone-passな場合の評価器
+}
+
+impl Regex {
+    /// 正規表現をパースしてコード生成し、Regexを生成する。
+    ///
+    /// マッチングには深さ優先探索（[`Engine::DepthFirst`]）を利用する。
+    /// 設定を変更する場合は[`RegexBuilder`]を利用すること。
+    ///
+    /// # 返り値
+    ///
+    /// 入力された正規表現にエラーがあったり、内部的な実装エラーがある場合はErrを返す。
+    pub fn new(expr: &str) -> Result<Regex, DynError> {
+        RegexBuilder::new(expr).build()
+    }
+
+    /// マッチングに深さ優先探索を利用するか設定する。
+    ///
+    /// is_depthがtrueの場合は深さ優先探索を、falseの場合は幅優先探索を利用
+    ///
+    /// # 返り値
+    ///
+    /// 正規表現が設定した評価器でサポートされない機能を含む場合はErrを返す。
+    pub fn depth_first(self, is_depth: bool) -> Result<Regex, DynError> {
+        self.engine(if is_depth {
+            Engine::DepthFirst
+        } else {
+            Engine::WidthFirst
+        })
+    }
+
+    /// マッチングに利用する評価器を設定する。
+    ///
+    /// 遅延DFAやone-passな評価器は利用せず、設定した評価器で評価する。
+    ///
+    /// # 返り値
+    ///
+    /// 後方参照を含む正規表現に後方参照をサポートしない評価器を設定した場合など、
+    /// 正規表現が設定した評価器でサポートされない機能を含む場合はErrを返す。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex_engine::{Engine, Regex};
+    /// let re = Regex::new("(a*)*b").unwrap().engine(Engine::Backtrack).unwrap();
+    /// assert!(re.is_match("aaaaab"));
+    /// assert!(Regex::new("(a)\\1").unwrap().engine(Engine::WidthFirst).is_err());
+    /// ```
+    pub fn engine(mut self, engine: Engine) -> Result<Regex, DynError> {
+        evaluator::check_engine(&self.code, engine)?;
+        self.engine = engine;
+        // 明示的に設定された評価器の代わりに遅延DFAやone-passな評価器を使わない
+        self.dfa = None;
+        self.full_dfa = None;
+        self.onepass = None;
+        Ok(self)
+    }
+
+    /// 元の正規表現を返す。
+    pub fn as_str(&self) -> &str {
+        &self.expr
+    }
+
+    /// 保持しているASTと命令列を標準出力に表示。
+    pub fn print(&self) {
+        println!("expr: {}\nAST:", self.expr);
+        println!("{}", self.ast);
+
+        println!();
+        println!("code:");
+        for (n, c) in self.code.iter().enumerate() {
+            println!("{:>04}: {}", n, c);
+        }
+    }
+
+    /// lineの先頭からマッチングを行う。
+    ///
+    /// エラーなく実行でき、かつマッチングに**成功**した場合はOk(true)を返す。
+    pub fn is_match_at_start(&self, line: &str) -> Result<bool, DynError> {
+        let line: Vec<char> = line.chars().collect();
+        if let Some(matched) = dfa_is_match(&self.dfa, &self.code, &line, true) {
+            return Ok(matched);
+        }
+        Ok(evaluator::eval(&self.code, &line, self.engine)?)
+    }
+
+    /// line全体が正規表現にマッチするか判定する。
+    ///
+    /// 内部的な実装エラーが発生した場合はfalseを返す。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex_engine::Regex;
+    /// let re = Regex::new("a|ab").unwrap();
+    /// assert!(re.full_match("ab"));
+    /// assert!(!re.full_match("abc"));
+    /// ```
+    pub fn full_match(&self, line: &str) -> bool {
+        let line: Vec<char> = line.chars().collect();
+        if let Some(matched) = dfa_is_match(&self.full_dfa, &self.full_code, &line, true) {
+            return matched;
+        }
+        evaluator::eval(&self.full_code, &line, self.engine).unwrap_or(false)
+    }
+
+    /// lineのいずれかの位置から正規表現にマッチするか判定する。
+    ///
+    /// 内部的な実装エラーが発生した場合はfalseを返す。
+    pub fn is_match(&self, line: &str) -> bool {
+        let chars: Vec<char> = line.chars().collect();
+        if let Some(dfa) = &self.dense_dfa {
+            return dfa.is_match(&chars);
+        }
+        if let Some(matched) = dfa_is_match(&self.dfa, &self.code, &chars, false) {
+            return matched;
+        }
+        self.find(line).is_some()
+    }
+
+    /// lineの中から最左のマッチを探索し、マッチした範囲を返す。
+    ///
+    /// 開始位置の走査は評価器の内部で行われる。
+    /// マッチしなかった場合や、内部的な実装エラーが発生した場合はNoneを返す。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex_engine::Regex;
+    /// let re = Regex::new("(ab|cd)+").unwrap();
+    /// let m = re.find("xxabcdx").unwrap();
+    /// assert_eq!((m.start(), m.end()), (2, 6));
+    /// assert_eq!(m.as_str(), "abcd");
+    /// ```
+    pub fn find<'t>(&self, line: &'t str) -> Option<Match<'t>> {
+        self.captures(line)?.get(0)
+    }
+
+    /// lineの中から最左のマッチを探索し、キャプチャグループごとの範囲を返す。
+    ///
+    /// マッチしなかった場合や、内部的な実装エラーが発生した場合はNoneを返す。
+    ///
+    /// # 利用例
+    ///
+    /// ```
+    /// use regex_engine::Regex;
+    /// let re = Regex::new("(a+)(b|c)").unwrap();
+    /// let caps = re.captures("xaac").unwrap();
+    /// assert_eq!(caps.get(0).unwrap().as_str(), "aac");
+    /// assert_eq!(caps.get(1).unwrap().as_str(), "aa");
+    /// assert_eq!(caps.get(2).unwrap().range(), 3..4);
+    /// ```
+    pub fn captures<'t>(&self, line: &'t str) -> Option<Captures<'t>> {
+        let chars: Vec<char> = line.chars().collect();
+        // マッチしないことがDFAでわかる場合は、評価器での探索を省く
+        if let Some(dfa) = &self.dense_dfa {
+            if !dfa.is_match(&chars) {
+                return None;
+            }
+        } else if dfa_is_match(&self.dfa, &self.code, &chars, false) == Some(false) {
+            return None;
+        }
+        let slots = match self.onepass_search(&chars) {
+            Some(slots) => slots?,
+            None => evaluator::search(&self.code, &chars, self.engine).ok()??,
+        };
+
+        // 文字単位のindexをバイト単位のオフセットに変換
+        let offsets: Vec<usize> = line
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(line.len()))
+            .collect();
+        // 繰り返しの開始位置を保存したスロットは除く
+        let slots = slots
+            .into_iter()
+            .take(self.captures_len() * 2)
+            .map(|slot| slot.map(|i| offsets[i]))
+            .collect();
+        Some(Captures {
+            text: line,
+            slots,
+            names: self.names.clone(),
+        })
+    }
+
+    /// one-passな評価器で、lineの中から最左のマッチを探索する。
+    ///
+    /// one-passな評価器がない場合や、開始位置を求める遅延DFAが評価を諦めた場合はNoneを返す。
+    fn onepass_search(&self, line: &[char]) -> Option<Option<Vec<Option<usize>>>> {
+        let onepass = self.onepass.as_ref()?;
+        let start = if onepass.is_anchored() {
+            0
+        } else {
+            // 逆順にした入力で最も後ろで終わるマッチが、元の入力での最左のマッチとなる
+            let reversed: Vec<char> = line.iter().rev().copied().collect();
+            let dfa = self.reverse_dfa.as_ref()?;
+            match dfa.last_match_end(&self.reverse_code, &reversed)? {
+                Some(end) => line.len() - end,
+                None => return Some(None),
+            }
+        };
+        Some(onepass.search(&self.code, line, start))
+    }
+
+    /// 0番目（マッチ全体）を含むキャプチャグループの数を返す。
+    pub fn captures_len(&self) -> usize {
+        self.ast.max_capture() + 1
+    }
+
+    /// 名前付きキャプチャグループの番号を返す。
+    ///
+    /// 指定した名前のグループが存在しない場合はNoneを返す。
+    pub fn capture_index(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+}
+
+/// 遅延DFAがある場合は、遅延DFAでlineがinstにマッチするか判定する。
+///
+/// 遅延DFAがない場合や、遅延DFAが評価を諦めた場合はNoneを返す。
+fn dfa_is_match(
+    dfa: &Option<LazyDfa>,
+    inst: &[Instruction],
+    line: &[char],
+    anchored: bool,
+) -> Option<bool> {
+    dfa.as_ref()?.is_match(inst, line, anchored)
+}
+
+/// 設定を指定してRegexを生成するビルダー。
+///
+/// # 利用例
+///
+/// ```
+/// use regex_engine::{Engine, RegexBuilder};
+/// let re = RegexBuilder::new("a{2,3}b")
+///     .engine(Engine::WidthFirst)
+///     .size_limit(100)
+///     .build()
+///     .unwrap();
+/// assert!(re.is_match("xaaab"));
+///
+/// // 命令数が上限を超える場合はエラー
+/// assert!(RegexBuilder::new("a{1000}").size_limit(100).build().is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct RegexBuilder {
+    expr: String,
+    engine: Option<Engine>, // 明示的に設定された評価器
+    size_limit: usize,
+    dfa_cache_capacity: usize,
+    dfa: bool,
+    dfa_state_limit: usize,
+    onepass: bool,
+    flags: parser::Flags,
+}
+
+impl RegexBuilder {
+    /// デフォルトの設定でビルダーを生成する。
+    pub fn new(expr: &str) -> RegexBuilder {
+        RegexBuilder {
+            expr: expr.to_string(),
+            engine: None,
+            size_limit: codegen::DEFAULT_SIZE_LIMIT,
+            dfa_cache_capacity: lazy_dfa::DEFAULT_CACHE_CAPACITY,
+            dfa: false,
+            dfa_state_limit: dfa::DEFAULT_STATE_LIMIT,
+            onepass: true,
+            flags: parser::Flags::default(),
+        }
+    }
+
+    /// マッチングに利用する評価器を設定する。
+    ///
+    /// 後方参照を含む正規表現に、後方参照をサポートしない評価器を設定した場合はbuildがエラーを返す。
+    /// 評価器を設定した場合、遅延DFAやone-passな評価器は利用せず、設定した評価器で評価する。
+    /// ただし、[`RegexBuilder::dfa`]で構築したDFAは利用する。
+    ///
+    /// ```
+    /// use regex_engine::{Engine, RegexBuilder};
+    /// let builder = RegexBuilder::new("(\\w+) \\1");
+    /// assert!(builder.clone().build().unwrap().is_match("hello hello"));
+    /// assert!(builder.engine(Engine::WidthFirst).build().is_err());
+    /// ```
+    pub fn engine(mut self, engine: Engine) -> RegexBuilder {
+        self.engine = Some(engine);
+        self
+    }
+
+    /// 生成する命令数の上限を設定する。
+    ///
+    /// `a{1000}{1000}`のように回数を指定した繰り返しは展開してコード生成されるため、
+    /// 命令数がこの上限を超える場合はbuildがエラーを返す。
+    pub fn size_limit(mut self, size_limit: usize) -> RegexBuilder {
+        self.size_limit = size_limit;
+        self
+    }
+
+    /// 遅延DFAのキャッシュに保持する状態数の上限を設定する。
+    ///
+    /// 状態数が上限に達するとキャッシュを破棄して決定化をやり直し、
+    /// 破棄が繰り返される場合はデフォルトの評価器での評価に切り替える。
+    /// 0を設定した場合や、[`RegexBuilder::engine`]で評価器を明示的に設定した場合は遅延DFAを利用しない。
+    pub fn dfa_cache_capacity(mut self, capacity: usize) -> RegexBuilder {
+        self.dfa_cache_capacity = capacity;
+        self
+    }
+
+    /// 生成時にDFAを構築するか設定する。
+    ///
+    /// trueの場合、部分集合構成法で構築して最小化したDFAを保持し、
+    /// [`Regex::is_match`]は表を引くだけで判定を行う。デフォルトはfalse。
+    /// 後方参照、先読み・後読み、アトミックグループを含む正規表現ではDFAを構築しない。
+    ///
+    /// ```
+    /// use regex_engine::RegexBuilder;
+    /// let re = RegexBuilder::new("(a|b)*abb").dfa(true).build().unwrap();
+    /// assert!(re.is_match("babaabb"));
+    ///
+    /// // 状態数が上限を超える場合はエラー
+    /// let builder = RegexBuilder::new("(a|b)*a(a|b){12}").dfa(true);
+    /// assert!(builder.dfa_state_limit(1000).build().is_err());
+    /// ```
+    pub fn dfa(mut self, yes: bool) -> RegexBuilder {
+        self.dfa = yes;
+        self
+    }
+
+    /// 生成時に構築するDFAの、最小化前の状態数の上限を設定する。
+    ///
+    /// 状態数がこの上限を超える場合はbuildがエラーを返す。
+    pub fn dfa_state_limit(mut self, limit: usize) -> RegexBuilder {
+        self.dfa_state_limit = limit;
+        self
+    }
+
+    /// one-passな正規表現で、one-passな評価器を利用するか設定する。
+    ///
+    /// one-passな正規表現とは、`key=(\w+);`のように、各位置で次の文字を消費できる分岐が高々1つに定まるものをいう。
+    /// trueの場合、one-passな正規表現のキャプチャグループの位置は、スレッドのリストやバックトラックを用いず、
+    /// 入力の1回の走査で求める。デフォルトはtrue。
+    /// 入力の先頭にアンカーされていない場合は、逆順にした正規表現の遅延DFAで入力を末尾から走査し、
+    /// 最左のマッチの開始位置を求めてから評価する。
+    /// [`RegexBuilder::engine`]で評価器を明示的に設定した場合や、
+    /// アンカーされていない正規表現で[`RegexBuilder::dfa_cache_capacity`]に0を設定した場合は利用しない。
+    pub fn onepass(mut self, yes: bool) -> RegexBuilder {
+        self.onepass = yes;
+        self
+    }
+
+    /// 複数行モードを設定する。
+    ///
+    /// trueの場合、`^`と`$`は入力の先頭と末尾に加えて、各行の先頭と末尾にもマッチする。
+    /// falseの場合は、`\A`と`\z`と同様に入力の先頭と末尾にのみマッチする。
+    /// 正規表現中の`(?m)`や`(?-m)`で部分的に変更できる。
+    pub fn multi_line(mut self, yes: bool) -> RegexBuilder {
+        self.flags.multi_line = yes;
+        self
+    }
+
+    /// Unicodeモードを設定する。
+    ///
+    /// trueの場合、`\b`と`\B`が用いる単語構成文字はUnicodeの文字、結合文字、数字、連結用の句読点などとなり、
+    /// `\d`、`\w`、`\s`もUnicodeの数字、単語構成文字、空白文字となる。
+    /// falseの場合は、いずれもASCIIの文字のみとなる。デフォルトはtrue。
+    pub fn unicode(mut self, yes: bool) -> RegexBuilder {
+        self.flags.unicode = yes;
+        self
+    }
+
+    /// `.`が改行にもマッチするか設定する。
+    ///
+    /// trueの場合、`.`は改行を含む任意の1文字にマッチする。
+    /// falseの場合は改行以外の任意の1文字にマッチする。デフォルトはfalse。
+    /// 正規表現中の`(?s)`や`(?-s)`で部分的に変更できる。
+    pub fn dot_matches_new_line(mut self, yes: bool) -> RegexBuilder {
+        self.flags.dot_matches_new_line = yes;
+        self
+    }
+
+    /// 大文字と小文字を区別しないモードを設定する。
+    ///
+    /// trueの場合、文字と文字クラスはUnicodeの単純ケースフォールディングで同一視される文字にもマッチする。
+    /// 正規表現中の`(?i)`と同様。Unicodeモードがfalseの場合は、ASCIIの英字のみが対象となる。
+    pub fn case_insensitive(mut self, yes: bool) -> RegexBuilder {
+        self.flags.case_insensitive = yes;
+        self
+    }
+
+    /// 正規表現をパースしてバイト単位の入力向けにコード生成し、BytesRegexを生成する。
+    ///
+    /// 遅延DFAやone-passな評価器などの設定は利用せず、設定された評価器でマッチングを行う。
+    ///
+    /// # 返り値
+    ///
+    /// 入力された正規表現にエラーがあったり、内部的な実装エラーがある場合はErrを返す。
+    pub fn build_bytes(&self) -> Result<BytesRegex, DynError> {
+        let ast = parser::parse(&self.expr, self.flags)?;
+        let code = codegen::get_byte_code(&ast, self.size_limit)?;
+        let engine = self.engine.unwrap_or_default();
+        evaluator::check_engine(&code, engine)?;
+        Ok(BytesRegex {
+            expr: self.expr.clone(),
+            code,
+            engine,
+        })
+    }
+
+    /// 正規表現をパースしてコード生成し、Regexを生成する。
+    ///
+    /// # 返り値
+    ///
+    /// 入力された正規表現にエラーがあったり、内部的な実装エラーがある場合はErrを返す。
+    pub fn build(&self) -> Result<Regex, DynError> {
+        let ast = parser::parse(&self.expr, self.flags)?;
+        let code = codegen::get_code(&ast, self.size_limit)?;
+        let engine = self.engine.unwrap_or_default();
+        evaluator::check_engine(&code, engine)?;
+        let full_code = codegen::get_full_code(&ast, self.size_limit)?;
+        let names = Arc::new(ast.capture_names());
+
+        // 遅延DFAはキャプチャグループの位置を求めないため、キャプチャグループを含まない場合のみ利用する
+        let (dfa, full_dfa) = if ast.max_capture() == 0 && self.engine.is_none() {
+            (
+                LazyDfa::new(&code, self.dfa_cache_capacity),
+                LazyDfa::new(&full_code, self.dfa_cache_capacity),
+            )
+        } else {
+            (None, None)
+        };
+
+        let dense_dfa = if self.dfa {
+            Dfa::new(&code, self.dfa_state_limit)?
+        } else {
+            None
+        };
+
+        let onepass = if self.onepass && self.engine.is_none() {
+            OnePass::new(&code)
+        } else {
+            None
+        };
+
+        // アンカーされていないone-passな正規表現では、マッチの開始位置を逆順にした正規表現で求める
+        let reversed = onepass
+            .as_ref()
+            .filter(|onepass| !onepass.is_anchored())
+            .and_then(|_| ast.reversed());
+        let (reverse_code, reverse_dfa) = match reversed {
+            Some(reversed) => {
+                let reverse_code = codegen::get_code(&reversed, self.size_limit)?;
+                let reverse_dfa = LazyDfa::new(&reverse_code, self.dfa_cache_capacity);
+                (reverse_code, reverse_dfa)
+            }
+            None => (Vec::new(), None),
+        };
+
+        Ok(Regex {
+            expr: self.expr.clone(),
+            ast,
+            code,
+            full_code,
+            reverse_code,
+            engine,
+            names,
+            dfa,
+            full_dfa,
+            reverse_dfa,
+            dense_dfa,
+            onepass,
+        })
+    }
+}
+
+/// バイト単位の入力とマッチングを行う、コンパイル済みの正規表現。
+///
+/// 正規表現中の文字と文字クラスは、そのUTF-8のバイト列にマッチするようコード生成される。
+/// そのため入力を事前に文字へ復号する必要がなく、不正なUTF-8を含むバイナリのログなども探索できる。
+/// 不正なバイトは、`.`を含むどの文字にもマッチしない。
+///
+/// # 利用例
+///
+/// ```
+/// use regex_engine::BytesRegex;
+/// let re = BytesRegex::new("é+").unwrap();
+/// let m = re.find(b"\xFFcaf\xC3\xA9\xC3\xA9!").unwrap();
+/// assert_eq!(m.range(), 4..8);
+/// assert!(!re.is_match(b"caf\xE9"));
+/// ```
+#[derive(Debug)]
+pub struct BytesRegex {
+    expr: String,
+    code: Vec<Instruction>,
+    engine: Engine,
+}
+
+impl BytesRegex {
+    /// 正規表現をパースしてバイト単位の入力向けにコード生成し、BytesRegexを生成する。
+    ///
+    /// 設定を変更する場合は[`RegexBuilder::build_bytes`]を利用すること。
+    ///
+    /// # 返り値
+    ///
+    /// 入力された正規表現にエラーがあったり、内部的な実装エラーがある場合はErrを返す。
+    pub fn new(expr: &str) -> Result<BytesRegex, DynError> {
+        RegexBuilder::new(expr).build_bytes()
+    }
+
+    /// マッチングに利用する評価器を設定する。
+    ///
+    /// # 返り値
+    ///
+    /// 正規表現が設定した評価器でサポートされない機能を含む場合はErrを返す。
+    pub fn engine(mut self, engine: Engine) -> Result<BytesRegex, DynError> {
+        evaluator::check_engine(&self.code, engine)?;
+        self.engine = engine;
+        Ok(self)
+    }
+
+    /// 元の正規表現を返す。
+    pub fn as_str(&self) -> &str {
+        &self.expr
+    }
+
+    /// lineの先頭からマッチングを行う。
+    ///
+    /// エラーなく実行でき、かつマッチングに**成功**した場合はOk(true)を返す。
+    pub fn is_match_at_start(&self, line: &[u8]) -> Result<bool, DynError> {
+        Ok(evaluator::eval(&self.code, line, self.engine)?)
+    }
+
+    /// lineのいずれかの位置から正規表現にマッチするか判定する。
+    ///
+    /// 内部的な実装エラーが発生した場合はfalseを返す。
+    pub fn is_match(&self, line: &[u8]) -> bool {
+        self.find(line).is_some()
+    }
+
+    /// lineの中から最左のマッチを探索し、マッチした範囲を返す。
+    ///
+    /// マッチしなかった場合や、内部的な実装エラーが発生した場合はNoneを返す。
+    pub fn find<'t>(&self, line: &'t [u8]) -> Option<BytesMatch<'t>> {
+        let slots = evaluator::search(&self.code, line, self.engine).ok()??;
+        match (slots.first()?, slots.get(1)?) {
+            (Some(start), Some(end)) => Some(BytesMatch {
+                bytes: line,
+                start: *start,
+                end: *end,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// バイト単位の入力でマッチした範囲を表す型。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BytesMatch<'t> {
+    bytes: &'t [u8],
+    start: usize,
+    end: usize,
+}
+
+impl<'t> BytesMatch<'t> {
+    /// マッチの開始位置。
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// マッチの終了位置。
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// マッチした範囲。
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+
+    /// マッチした部分のバイト列。
+    pub fn as_bytes(&self) -> &'t [u8] {
+        &self.bytes[self.start..self.end]
+    }
+}
+
+/// マッチした範囲を表す型。
+///
+/// 開始位置と終了位置は、マッチ対象の文字列におけるバイト単位のオフセット。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match<'t> {
+    text: &'t str,
+    start: usize,
+    end: usize,
+}
+
+impl<'t> Match<'t> {
+    /// マッチの開始位置。
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// マッチの終了位置。
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// マッチした範囲。
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+
+    /// マッチした部分文字列。
+    pub fn as_str(&self) -> &'t str {
+        &self.text[self.start..self.end]
+    }
+}
+
+/// キャプチャグループごとにマッチした範囲を表す型。
+///
+/// 0番目のグループはマッチ全体を表す。
+/// 名前付きキャプチャグループは、番号と名前のどちらでも参照できる。
+///
+/// # 利用例
+///
+/// ```
+/// use regex_engine::Regex;
+/// let re = Regex::new("(?P<key>\\w+)=(?<value>\\w+)").unwrap();
+/// let caps = re.captures("x: size=10").unwrap();
+/// assert_eq!(caps.name("key").unwrap().as_str(), "size");
+/// assert_eq!(caps.get(2).unwrap().as_str(), "10");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures<'t> {
+    text: &'t str,
+    slots: Vec<Option<usize>>,
+    names: Arc<HashMap<String, usize>>,
+}
+
+impl<'t> Captures<'t> {
+    /// i番目のキャプチャグループにマッチした範囲を返す。
+    ///
+    /// グループが存在しない場合や、グループがマッチに参加しなかった場合はNoneを返す。
+    pub fn get(&self, i: usize) -> Option<Match<'t>> {
+        match (self.slots.get(i * 2)?, self.slots.get(i * 2 + 1)?) {
+            (Some(start), Some(end)) => Some(Match {
+                text: self.text,
+                start: *start,
+                end: *end,
+            }),
+            _ => None,
+        }
+    }
+
+    /// nameという名前のキャプチャグループにマッチした範囲を返す。
+    ///
+    /// グループが存在しない場合や、グループがマッチに参加しなかった場合はNoneを返す。
+    pub fn name(&self, name: &str) -> Option<Match<'t>> {
+        self.get(*self.names.get(name)?)
+    }
+
+    /// 0番目（マッチ全体）を含むキャプチャグループの数を返す。
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    /// キャプチャグループが存在しない場合にtrueを返す。
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
@@ -8,8 +8,15 @@
 //! let line = "cdefdefdef";
 //! regex_engine::do_matching(expr, line, true);
 //! regex_engine::print(expr);
+//!
+//! // パースとコード生成を一度だけ行い、繰り返しマッチングする場合
+//! let re = regex_engine::Regex::new(expr).unwrap();
+//! assert!(re.is_match(line));
 //! ```
 mod engine;
 mod helper;
 
-pub use engine::{do_matching, print};
+pub use engine::{
+    BytesMatch, BytesRegex, Captures, Engine, Match, Regex, RegexBuilder, do_matching,
+    do_matching_bytes, print,
+};
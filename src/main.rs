@@ -1,7 +1,7 @@
-mod engine;
 mod helper;
 
 use helper::DynError;
+use regex_engine::Regex;
 use std::{
     env,
     fs::File,
@@ -22,31 +22,21 @@ fn main() -> Result<(), DynError> {
 
 /// ファイルをオープンし、行ごとにマッチングを行う。
 ///
-/// マッチングはそれぞれの行頭から1文字ずつずらして行い、
-/// いずれかのマッチした場合に、その行がマッチしたものとみなす。
-///
-/// たとえば、abcdという文字列があった場合、以下の順にマッチが行われ、
-/// このいずれかにマッチした場合、与えられた正規表現にマッチする行と判定する。
-///
-/// - abcd
-/// - bcd
-/// - cd
-/// - d
+/// 正規表現のパースとコード生成は最初に一度だけ行い、
+/// いずれかの位置でマッチした場合に、その行がマッチしたものとみなす。
 fn match_file(expr: &str, file: &str) -> Result<(), DynError> {
     let f = File::open(file)?;
     let reader = BufReader::new(f);
 
-    engine::print(expr)?;
+    regex_engine::print(expr)?;
     println!();
 
+    let re = Regex::new(expr)?;
+
     for line in reader.lines() {
         let line = line?;
-        for (i, _) in line.char_indices() {
-            // UTF-8の文字単位でのindexを取得
-            if engine::do_matching(expr, &line[i..], true)? {
-                println!("{}", line);
-                break;
-            }
+        if re.is_match(&line) {
+            println!("{}", line);
         }
     }
 
@@ -56,10 +46,10 @@ fn match_file(expr: &str, file: &str) -> Result<(), DynError> {
 // 単体テスト
 #[cfg(test)]
 mod tests {
-    use crate::{
-        engine::do_matching,
-        helper::{SafeAdd, safe_add},
-    };
+    use crate::helper::{SafeAdd, safe_add};
+    use regex_engine::{BytesRegex, Engine, Regex, RegexBuilder, do_matching, do_matching_bytes};
+
+    const ENGINES: [Engine; 3] = [Engine::DepthFirst, Engine::WidthFirst, Engine::Backtrack];
 
     #[test]
     fn test_safe_add() {
@@ -100,4 +90,1304 @@ mod tests {
         assert!(!do_matching("(ab|cd)+", "", true).unwrap());
         assert!(!do_matching("abc?", "acb", true).unwrap());
     }
+
+    #[test]
+    fn test_regex() {
+        // パースエラー
+        assert!(Regex::new("+b").is_err());
+        assert!(Regex::new("(ab").is_err());
+
+        let re = Regex::new("abc|(de|cd)+").unwrap();
+        assert_eq!(re.as_str(), "abc|(de|cd)+");
+
+        // 先頭からのマッチング
+        assert!(re.is_match_at_start("decddede").unwrap());
+        assert!(!re.is_match_at_start("xabc").unwrap());
+
+        // いずれかの位置でのマッチング
+        assert!(re.is_match("xabc"));
+        assert!(re.is_match("xxcd"));
+        assert!(!re.is_match("xxx"));
+
+        // 幅優先探索でも同じ結果となる
+        let re = Regex::new("abc|(de|cd)+")
+            .unwrap()
+            .depth_first(false)
+            .unwrap();
+        assert!(re.is_match("xabc"));
+        assert!(!re.is_match("xxx"));
+    }
+
+    #[test]
+    fn test_find() {
+        for engine in ENGINES {
+            let re = Regex::new("(ab|cd)+").unwrap().engine(engine).unwrap();
+            let m = re.find("xxabcdx").unwrap();
+            assert_eq!((m.start(), m.end()), (2, 6));
+            assert_eq!(m.as_str(), "abcd");
+            assert!(re.find("xxx").is_none());
+
+            // 最左のマッチを返す
+            let re = Regex::new("b|ab").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("cab").unwrap().range(), 1..3);
+
+            // バイト単位のオフセットを返す
+            let re = Regex::new("いう").unwrap().engine(engine).unwrap();
+            let m = re.find("あいうえお").unwrap();
+            assert_eq!(m.range(), 3..9);
+            assert_eq!(m.as_str(), "いう");
+
+            // 空文字列へのマッチ
+            let re = Regex::new("a*").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("bbb").unwrap().range(), 0..0);
+        }
+    }
+
+    #[test]
+    fn test_captures() {
+        for engine in ENGINES {
+            let re = Regex::new("(a+)(b|(c))d").unwrap().engine(engine).unwrap();
+            assert_eq!(re.captures_len(), 4);
+
+            let caps = re.captures("xaabd").unwrap();
+            assert_eq!(caps.len(), 4);
+            assert_eq!(caps.get(0).unwrap().as_str(), "aabd");
+            assert_eq!(caps.get(1).unwrap().range(), 1..3);
+            assert_eq!(caps.get(2).unwrap().as_str(), "b");
+            // マッチに参加しなかったグループ
+            assert!(caps.get(3).is_none());
+            // 存在しないグループ
+            assert!(caps.get(4).is_none());
+
+            let caps = re.captures("acd").unwrap();
+            assert_eq!(caps.get(2).unwrap().as_str(), "c");
+            assert_eq!(caps.get(3).unwrap().as_str(), "c");
+
+            // 繰り返しの場合は最後にマッチした範囲を保存する
+            let re = Regex::new("(ab|cd)+").unwrap().engine(engine).unwrap();
+            let caps = re.captures("abcdab").unwrap();
+            assert_eq!(caps.get(1).unwrap().range(), 4..6);
+
+            // 空のグループは空文字列をキャプチャする
+            let re = Regex::new("a()b").unwrap().engine(engine).unwrap();
+            assert_eq!(re.captures("ab").unwrap().get(1).unwrap().range(), 1..1);
+        }
+    }
+
+    #[test]
+    fn test_width_first() {
+        // 入れ子になった繰り返しでも停止する
+        assert!(do_matching("((((a*)*)*)*)", "aaaaaaaaa", false).unwrap());
+        assert!(do_matching("(a*)*b", "aaaaaaaaab", false).unwrap());
+        assert!(do_matching("(a*)*b", "b", false).unwrap());
+        assert!(do_matching("a**b", "aaaaaaaaab", false).unwrap());
+        assert!(!do_matching("(a*)*b", "aaaaaaaaa", false).unwrap());
+
+        // a?^n a^nでも線形時間でマッチする
+        let n = 100;
+        let expr = format!("{}{}", "a?".repeat(n), "a".repeat(n));
+        let line = "a".repeat(n);
+        assert!(do_matching(&expr, &line, false).unwrap());
+
+        let re = Regex::new(&expr).unwrap().depth_first(false).unwrap();
+        let line = format!("b{}b", line);
+        assert_eq!(re.find(&line).unwrap().range(), 1..n + 1);
+
+        // 深さ優先探索と同じ優先度でマッチする
+        let re = Regex::new("(a|ab)(c|bcd)")
+            .unwrap()
+            .depth_first(false)
+            .unwrap();
+        let caps = re.captures("abcd").unwrap();
+        assert_eq!(caps.get(0).unwrap().as_str(), "abcd");
+        assert_eq!(caps.get(1).unwrap().as_str(), "a");
+    }
+
+    #[test]
+    fn test_backtrack() {
+        // 入れ子になった繰り返しでも停止する
+        let re = Regex::new("((((a*)*)*)*)")
+            .unwrap()
+            .engine(Engine::Backtrack)
+            .unwrap();
+        assert!(re.is_match_at_start("aaaaaaaaa").unwrap());
+        let re = Regex::new("(a*)*b")
+            .unwrap()
+            .engine(Engine::Backtrack)
+            .unwrap();
+        assert!(re.is_match_at_start("aaaaaaaaab").unwrap());
+        assert!(re.is_match_at_start("b").unwrap());
+        assert!(!re.is_match("aaaaaaaaa"));
+
+        // a?^n a^nでも多項式時間でマッチし、スタックも溢れない
+        let n = 100;
+        let expr = format!("{}{}", "a?".repeat(n), "a".repeat(n));
+        let re = Regex::new(&expr)
+            .unwrap()
+            .engine(Engine::Backtrack)
+            .unwrap();
+        assert!(re.is_match_at_start(&"a".repeat(n)).unwrap());
+        assert!(!re.is_match(&"a".repeat(n - 1)));
+
+        let re = Regex::new("(a*)*c")
+            .unwrap()
+            .engine(Engine::Backtrack)
+            .unwrap();
+        assert!(!re.is_match(&"a".repeat(10000)));
+
+        // 訪問済みの記録が上限を超える入力では、WidthFirstで評価する
+        let re = Regex::new("(a|b)*c")
+            .unwrap()
+            .engine(Engine::Backtrack)
+            .unwrap();
+        let line = "a".repeat(300_000);
+        assert!(!re.is_match(&line));
+        let line = format!("{line}bc");
+        let caps = re.captures(&line).unwrap();
+        assert_eq!(caps.get(0).unwrap().range(), 0..300_002);
+        assert_eq!(caps.get(1).unwrap().as_str(), "b");
+
+        // 深さ優先探索と同じ優先度でマッチする
+        let re = Regex::new("(a|ab)(c|bcd)(d*)")
+            .unwrap()
+            .engine(Engine::Backtrack)
+            .unwrap();
+        let caps = re.captures("abcd").unwrap();
+        assert_eq!(caps.get(1).unwrap().as_str(), "a");
+        assert_eq!(caps.get(2).unwrap().as_str(), "bcd");
+        assert_eq!(caps.get(3).unwrap().as_str(), "");
+    }
+
+    #[test]
+    fn test_empty_loop() {
+        // 入れ子になった繰り返しは、どの評価器でも停止する
+        let exprs = [
+            "(a*)*",
+            "(a*)+",
+            "(a?)*",
+            "(a?)+",
+            "(a+)*",
+            "(a*)?",
+            "((a*)*)*",
+            "((((a*)*)*)*)",
+            "(a*|b)*",
+            "(a|b*)+",
+            "()*",
+            "()+",
+            "a**",
+            "(a*)*(b*)*",
+        ];
+        for engine in ENGINES {
+            for expr in exprs {
+                let re = Regex::new(expr).unwrap().engine(engine).unwrap();
+                assert!(re.is_match_at_start("aaaa").unwrap(), "{}", expr);
+                assert!(re.is_match_at_start("").unwrap(), "{}", expr);
+                assert!(re.is_match_at_start("c").unwrap(), "{}", expr);
+
+                let re = Regex::new(&format!("{}c", expr))
+                    .unwrap()
+                    .engine(engine)
+                    .unwrap();
+                assert!(re.is_match_at_start("c").unwrap(), "{}c", expr);
+                assert!(re.is_match("aaac"), "{}c", expr);
+                assert!(!re.is_match("aaaa"), "{}c", expr);
+            }
+
+            // 入力を消費しなかった繰り返しは打ち切られる
+            let re = Regex::new("(a*)*").unwrap().engine(engine).unwrap();
+            assert!(re.captures("b").unwrap().get(1).is_none());
+            let re = Regex::new("(a*)+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.captures("b").unwrap().get(1).unwrap().range(), 0..0);
+            let re = Regex::new("(a|b*)*c").unwrap().engine(engine).unwrap();
+            assert_eq!(re.captures("abbc").unwrap().get(1).unwrap().range(), 1..3);
+            let re = Regex::new("((.)*)+").unwrap().engine(engine).unwrap();
+            let caps = re.captures("ab").unwrap();
+            assert_eq!(caps.get(1).unwrap().range(), 2..2);
+            assert_eq!(caps.get(2).unwrap().range(), 1..2);
+
+            // .は改行にマッチせず、停止する
+            let re = Regex::new("a.b").unwrap().engine(engine).unwrap();
+            assert!(!re.is_match("a\nb"));
+            let re = Regex::new("(.)*b").unwrap().engine(engine).unwrap();
+            assert!(!re.is_match_at_start("a\nb").unwrap());
+            assert!(re.is_match("a\nb"));
+        }
+
+        // 繰り返しの打ち切りはスロットの値によらず、どの評価器でもキャプチャの位置が一致する
+        let exprs = [
+            "((.)*)+",
+            "((a*)*)*",
+            "((a*)+)+b",
+            "(a|)+",
+            "(a|b*)*?c",
+            "((a)|(b*))*",
+            "(?:(a*)|(b))+",
+            "((a?)*)*",
+            "(()|a)+",
+        ];
+        let lines = ["", "ab", "aab", "bba", "abbc"];
+        for expr in exprs {
+            let depth = Regex::new(expr).unwrap();
+            for engine in ENGINES {
+                let re = Regex::new(expr).unwrap().engine(engine).unwrap();
+                for line in lines {
+                    assert_eq!(
+                        re.captures(line),
+                        depth.captures(line),
+                        "{expr} {line:?} {engine:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_class() {
+        // パースエラー
+        assert!(Regex::new("[abc").is_err());
+        assert!(Regex::new("[]").is_err());
+        assert!(Regex::new("[z-a]").is_err());
+        assert!(Regex::new("[a\\").is_err());
+        assert!(Regex::new("[\\y]").is_err());
+
+        for engine in ENGINES {
+            let re = Regex::new("[a-z][0-9]+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("AZb42x").unwrap().as_str(), "b42");
+            assert!(!re.is_match("A1"));
+
+            // 否定
+            let re = Regex::new("[^a-z]+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("abcDE\nFgh").unwrap().as_str(), "DE\nF");
+            assert!(!re.is_match("abc"));
+
+            // 先頭の]と、先頭または末尾の-はリテラル
+            let re = Regex::new("[]a]+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("x]a]y").unwrap().as_str(), "]a]");
+            let re = Regex::new("[^]]").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("]]a").unwrap().as_str(), "a");
+            let re = Regex::new("[-a]+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("b-a-").unwrap().as_str(), "-a-");
+            let re = Regex::new("[a-]+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("b-a-").unwrap().as_str(), "-a-");
+
+            // ブラケット内のエスケープ
+            let re = Regex::new("[\\]\\-\\^\\\\]+")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert_eq!(re.find("a]-^\\b").unwrap().as_str(), "]-^\\");
+            let re = Regex::new("[\\[-\\]]+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("a[\\]b").unwrap().as_str(), "[\\]");
+
+            // ブラケット外の[と]のエスケープ
+            let re = Regex::new("\\[[0-9]\\]").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("a[1]").unwrap().as_str(), "[1]");
+
+            // マルチバイト文字の範囲
+            let re = Regex::new("[ぁ-ん]+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("漢字かなカナ").unwrap().as_str(), "かな");
+        }
+    }
+
+    #[test]
+    fn test_repeat() {
+        // パースエラー
+        assert!(Regex::new("{2}").is_err());
+        assert!(Regex::new("a{2,1}").is_err());
+        assert!(Regex::new("a{99999999999}").is_err());
+
+        // 命令数の上限
+        assert!(Regex::new("a{1000}{1100}").is_err());
+        assert!(RegexBuilder::new("a{100}").size_limit(50).build().is_err());
+        assert!(RegexBuilder::new("a{100}").size_limit(200).build().is_ok());
+
+        for engine in ENGINES {
+            let re = Regex::new("a{3}").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("aaaaa").unwrap().as_str(), "aaa");
+            assert!(!re.is_match("aa"));
+
+            let re = Regex::new("ba{2,}").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("baaaaa").unwrap().as_str(), "baaaaa");
+            assert!(!re.is_match("ba"));
+
+            let re = Regex::new("ba{2,4}").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("baaaaa").unwrap().as_str(), "baaaa");
+            assert_eq!(re.find("baab").unwrap().as_str(), "baa");
+            assert!(!re.is_match("ba"));
+
+            let re = Regex::new("ba{0}c").unwrap().engine(engine).unwrap();
+            assert!(re.is_match("bc"));
+            assert!(!re.is_match("bac"));
+
+            // グループの繰り返しと、空文字列にマッチし得る式の繰り返し
+            let re = Regex::new("(ab|c){2,3}").unwrap().engine(engine).unwrap();
+            let caps = re.captures("xcabcab").unwrap();
+            assert_eq!(caps.get(0).unwrap().as_str(), "cabc");
+            assert_eq!(caps.get(1).unwrap().as_str(), "c");
+            let re = Regex::new("(a*){2,}b").unwrap().engine(engine).unwrap();
+            assert!(re.is_match_at_start("aaab").unwrap());
+
+            // 波括弧のエスケープ
+            let re = Regex::new("\\{[0-9]{2}\\}")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert_eq!(re.find("{1}{23}").unwrap().as_str(), "{23}");
+
+            // 繰り返しの回数が続かない波括弧は、通常の文字として扱う
+            for (expr, line) in [
+                ("a{", "a{"),
+                ("{foo}", "x{foo}"),
+                ("a{,2}", "a{,2}"),
+                ("a{1x}", "a{1x}"),
+            ] {
+                let re = Regex::new(expr).unwrap().engine(engine).unwrap();
+                assert_eq!(re.find(line).unwrap().as_str(), expr);
+            }
+            let re = Regex::new("{").unwrap().engine(engine).unwrap();
+            assert!(!re.is_match("a"));
+        }
+    }
+
+    #[test]
+    fn test_lazy() {
+        assert!(Regex::new("??").is_err());
+
+        for engine in ENGINES {
+            let re = Regex::new("<.*>").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("<a><b>").unwrap().as_str(), "<a><b>");
+            let re = Regex::new("<.*?>").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("<a><b>").unwrap().as_str(), "<a>");
+
+            let re = Regex::new("a+?").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("aaa").unwrap().as_str(), "a");
+            let re = Regex::new("a+?b").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("aaab").unwrap().as_str(), "aaab");
+
+            let re = Regex::new("ab??").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("abb").unwrap().as_str(), "a");
+            let re = Regex::new("ab??c").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("abc").unwrap().as_str(), "abc");
+
+            let re = Regex::new("a{2,4}?").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("aaaa").unwrap().as_str(), "aa");
+            let re = Regex::new("a{2,}?").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("aaaa").unwrap().as_str(), "aa");
+
+            // 最短のマッチはキャプチャにも反映される
+            let re = Regex::new("(a*?)(a*)").unwrap().engine(engine).unwrap();
+            let caps = re.captures("aaa").unwrap();
+            assert_eq!(caps.get(1).unwrap().as_str(), "");
+            assert_eq!(caps.get(2).unwrap().as_str(), "aaa");
+
+            // 空文字列にマッチし得る式の最短の繰り返し
+            let re = Regex::new("(a*)+?b").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("aab").unwrap().as_str(), "aab");
+            let re = Regex::new("(a|b*)*?c").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("abbc").unwrap().as_str(), "abbc");
+
+            // 繰り返しの中の最短の繰り返しは、空の繰り返しが打ち切られるため入力を消費する
+            let re = Regex::new("(?:a*?)*").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("aa").unwrap().range(), 0..2);
+            let re = Regex::new("(a*?)*").unwrap().engine(engine).unwrap();
+            let caps = re.captures("aa").unwrap();
+            assert_eq!(caps.get(0).unwrap().range(), 0..2);
+            assert_eq!(caps.get(1).unwrap().range(), 1..2);
+        }
+    }
+
+    #[test]
+    fn test_anchor() {
+        // 先頭からのマッチングでも末尾は確認されない
+        assert!(do_matching("abc", "abcd", true).unwrap());
+        assert!(!do_matching("abc$", "abcd", true).unwrap());
+        assert!(do_matching("abc$", "abc", false).unwrap());
+
+        for engine in ENGINES {
+            let re = Regex::new("^ab").unwrap().engine(engine).unwrap();
+            assert!(re.is_match("abc"));
+            assert!(!re.is_match("cab"));
+
+            let re = Regex::new("ab$").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("abcab").unwrap().range(), 3..5);
+            assert!(!re.is_match("abc"));
+
+            let re = Regex::new("\\Aa+\\z").unwrap().engine(engine).unwrap();
+            assert!(re.is_match("aaa"));
+            assert!(!re.is_match("aaab"));
+            assert!(!re.is_match("baaa"));
+
+            // 複数行モードでない場合、^と$は行の境界にマッチしない
+            let re = Regex::new("^b$").unwrap().engine(engine).unwrap();
+            assert!(!re.is_match("a\nb\nc"));
+
+            // 複数行モード
+            let re = RegexBuilder::new("^b+$")
+                .multi_line(true)
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert_eq!(re.find("a\nbb\nc").unwrap().range(), 2..4);
+            assert!(re.is_match("bb"));
+            assert!(!re.is_match("abb\nc"));
+            let re = RegexBuilder::new("\\Ab")
+                .multi_line(true)
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(!re.is_match("a\nb"));
+
+            // 空文字列と繰り返し
+            let re = Regex::new("^$").unwrap().engine(engine).unwrap();
+            assert!(re.is_match(""));
+            assert!(!re.is_match("a"));
+            let re = Regex::new("(^)*a").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("ba").unwrap().range(), 1..2);
+
+            // ^と$のエスケープ
+            let re = Regex::new("\\^[0-9]\\$").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("a^1$").unwrap().as_str(), "^1$");
+
+            // 入力全体とのマッチング
+            let re = Regex::new("a|ab").unwrap().engine(engine).unwrap();
+            assert!(re.full_match("ab"));
+            assert!(re.full_match("a"));
+            assert!(!re.full_match("abc"));
+            assert!(!re.full_match("xab"));
+            let re = Regex::new("(a*)*").unwrap().engine(engine).unwrap();
+            assert!(re.full_match(""));
+            assert!(re.full_match("aaa"));
+            assert!(!re.full_match("aab"));
+        }
+    }
+
+    #[test]
+    fn test_word_boundary() {
+        for engine in ENGINES {
+            let re = Regex::new("\\bcat\\b").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("concat cat").unwrap().range(), 7..10);
+            assert!(re.is_match("cat"));
+            assert!(re.is_match("(cat)"));
+            assert!(!re.is_match("cats"));
+
+            let re = Regex::new("\\Bcat").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("cat concat").unwrap().range(), 7..10);
+            assert!(!re.is_match("cat"));
+
+            let re = Regex::new("\\b").unwrap().engine(engine).unwrap();
+            assert!(!re.is_match(""));
+            assert!(!re.is_match(" "));
+            let re = Regex::new("\\B").unwrap().engine(engine).unwrap();
+            assert!(re.is_match(""));
+
+            // Unicodeの単語構成文字
+            let re = Regex::new("\\bé\\b").unwrap().engine(engine).unwrap();
+            assert!(!re.is_match("café"));
+            assert!(re.is_match("à é"));
+            let re = Regex::new("\\b日本\\b").unwrap().engine(engine).unwrap();
+            assert!(!re.is_match("日本語"));
+            assert!(re.is_match("「日本」"));
+
+            // 結合文字も単語構成文字である
+            let re = Regex::new("e\\b").unwrap().engine(engine).unwrap();
+            assert!(!re.is_match("e\u{301}"));
+            let re = Regex::new("\\w+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("e\u{301}!").unwrap().as_str(), "e\u{301}");
+
+            // ASCIIの単語構成文字のみ
+            let re = RegexBuilder::new("\\bé\\b")
+                .unicode(false)
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(!re.is_match("café"));
+            let re = RegexBuilder::new("caf\\b")
+                .unicode(false)
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(re.is_match("café"));
+            let re = RegexBuilder::new("\\b_1\\b")
+                .unicode(false)
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(re.is_match("a _1 b"));
+        }
+    }
+
+    #[test]
+    fn test_perl_class() {
+        for engine in ENGINES {
+            let re = Regex::new("\\d+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("abc 2024-10").unwrap().as_str(), "2024");
+            let re = Regex::new("\\w+\\s*=\\s*\\w+")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert_eq!(re.find("  key =\tvalue;").unwrap().as_str(), "key =\tvalue");
+            let re = Regex::new("\\D\\W\\S").unwrap().engine(engine).unwrap();
+            assert!(re.full_match("a!b"));
+            assert!(!re.full_match("1!b"));
+            assert!(!re.full_match("a_b"));
+            assert!(!re.full_match("a! "));
+
+            // 角括弧の中でも利用でき、隣接する-はリテラル
+            let re = Regex::new("^[\\d\\s-]+$").unwrap().engine(engine).unwrap();
+            assert!(re.is_match("03 1234-5678"));
+            assert!(!re.is_match("03-abcd"));
+            let re = Regex::new("^[^\\w.]+$").unwrap().engine(engine).unwrap();
+            assert!(re.is_match("!? "));
+            assert!(!re.is_match("!.?"));
+
+            // Unicodeの定義
+            let re = Regex::new("^\\d+$").unwrap().engine(engine).unwrap();
+            assert!(re.is_match("١٢٣"));
+            assert!(!re.is_match("一二三"));
+            let re = Regex::new("^\\w+\\s\\w+$").unwrap().engine(engine).unwrap();
+            assert!(re.is_match("日本語\u{3000}café"));
+
+            // ASCIIのみの定義
+            let re = RegexBuilder::new("^\\w+\\s\\d+$")
+                .unicode(false)
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(re.is_match("abc_1 42"));
+            assert!(!re.is_match("café 42"));
+            assert!(!re.is_match("abc\u{3000}42"));
+            assert!(!re.is_match("abc ١٢"));
+        }
+        assert!(Regex::new("[\\b]").is_err());
+    }
+
+    #[test]
+    fn test_escape() {
+        for engine in ENGINES {
+            let re = Regex::new("a\\tb\\r\\n").unwrap().engine(engine).unwrap();
+            assert!(re.full_match("a\tb\r\n"));
+            let re = Regex::new("\\a\\e\\f\\v\\0")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert!(re.full_match("\x07\x1B\x0C\x0B\0"));
+            let re = Regex::new("\\x41\\x{42}\\u0043\\u{1F600}\\U0001F601\\U{e9}")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert!(re.full_match("ABC😀😁é"));
+            let re = Regex::new("^[\\x41-\\x{5A}\\t]+$")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert!(re.is_match("AZ\tQ"));
+            assert!(!re.is_match("AZ a"));
+        }
+
+        let err = |expr: &str| Regex::new(expr).unwrap_err().to_string();
+        assert_eq!(err("a\\x4"), "ParseError: incomplete hex escape: pos = 2");
+        assert_eq!(err("\\x{41"), "ParseError: incomplete hex escape: pos = 1");
+        assert_eq!(
+            err("\\xg1"),
+            "ParseError: invalid hex digit: pos = 2, char = g"
+        );
+        assert_eq!(
+            err("\\u{}"),
+            "ParseError: invalid hex digit: pos = 3, char = }"
+        );
+        assert_eq!(
+            err("\\u{110000}"),
+            "ParseError: invalid code point: pos = 1, code point = U+110000"
+        );
+        assert_eq!(
+            err("\\uD800"),
+            "ParseError: invalid code point: pos = 1, code point = U+D800"
+        );
+        assert_eq!(
+            err("\\x{123456789}"),
+            "ParseError: invalid code point: pos = 1, code point = U+123456789"
+        );
+    }
+
+    #[cfg(feature = "unicode-tables")]
+    #[test]
+    fn test_property() {
+        for engine in ENGINES {
+            let re = Regex::new("\\p{Han}+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("これは日本語です").unwrap().as_str(), "日本語");
+            let re = Regex::new("^\\pL+$").unwrap().engine(engine).unwrap();
+            assert!(re.is_match("Straßeδ日本"));
+            assert!(!re.is_match("abc1"));
+            let re = Regex::new("^\\p{ Uppercase Letter }\\p{gc=Ll}+$")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert!(re.is_match("Émile"));
+            assert!(!re.is_match("émile"));
+            let re = Regex::new("^[\\p{script=hiragana}\\p{Katakana}]+$")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert!(re.is_match("ひらがなカタカナ"));
+            assert!(!re.is_match("漢字"));
+
+            // 否定
+            let re = Regex::new("^\\P{Greek}+$").unwrap().engine(engine).unwrap();
+            assert!(re.is_match("abc"));
+            assert!(!re.is_match("aβc"));
+            let re = Regex::new("^[^\\p{N}\\s]+$")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert!(re.is_match("abc"));
+            assert!(!re.is_match("a\u{2167}c"));
+        }
+
+        let err = |expr: &str| Regex::new(expr).unwrap_err().to_string();
+        assert_eq!(
+            err("a\\p{Klingon}"),
+            "ParseError: unknown property: pos = 2, name = Klingon"
+        );
+        assert_eq!(
+            err("\\p{Greek"),
+            "ParseError: incomplete property name: pos = 1"
+        );
+        assert_eq!(
+            err("\\p{}"),
+            "ParseError: incomplete property name: pos = 1"
+        );
+        assert!(Regex::new("\\p{foo=Greek}").is_err());
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        for engine in ENGINES {
+            let re = Regex::new("(?i)hello, world")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert!(re.full_match("HeLLo, WORLD"));
+            let re = Regex::new("a(?i)b(c)d").unwrap().engine(engine).unwrap();
+            assert!(re.full_match("aBCD"));
+            assert!(!re.full_match("ABCD"));
+            // (?i)はグループの終わりまで有効
+            let re = Regex::new("((?i)a)a").unwrap().engine(engine).unwrap();
+            assert!(re.full_match("Aa"));
+            assert!(!re.full_match("AA"));
+
+            let re = Regex::new("(?i)[a-c]+[^x]\\x44")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert!(re.full_match("aBcyd"));
+            assert!(!re.full_match("abcXd"));
+
+            // Unicodeの単純ケースフォールディング
+            let re = Regex::new("(?i)straße σ k")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert!(re.full_match("STRAẞE Σ \u{212A}"));
+            assert!(!re.full_match("strasse ς k"));
+            let re = Regex::new("(?i)[^Σ]").unwrap().engine(engine).unwrap();
+            assert!(!re.full_match("ς"));
+
+            let re = RegexBuilder::new("ÉCOLE")
+                .case_insensitive(true)
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(re.full_match("école"));
+            // ASCIIのみのケースフォールディング
+            let re = RegexBuilder::new("ÉCOLE")
+                .case_insensitive(true)
+                .unicode(false)
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(re.full_match("ÉcolE"));
+            assert!(!re.full_match("école"));
+        }
+        assert!(Regex::new("(?y)a").is_err());
+        assert!(Regex::new("(?)a").is_err());
+        assert!(Regex::new("(?i").is_err());
+    }
+
+    #[test]
+    fn test_inline_flags() {
+        for engine in ENGINES {
+            // スコープ付きのフラグはキャプチャしないグループ
+            let re = Regex::new("a(?i:b)c(d)").unwrap().engine(engine).unwrap();
+            assert!(re.full_match("aBcd"));
+            assert!(!re.full_match("aBCd"));
+            assert_eq!(re.captures("aBcd").unwrap().get(1).unwrap().as_str(), "d");
+            let re = Regex::new("(?:ab)+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.captures("xababx").unwrap().len(), 1);
+            assert!(re.full_match("abab"));
+            let re = Regex::new("(?i)a(?-i:b)c").unwrap().engine(engine).unwrap();
+            assert!(re.full_match("AbC"));
+            assert!(!re.full_match("ABC"));
+
+            // 複数行モード
+            let re = Regex::new("(?m)^b$").unwrap().engine(engine).unwrap();
+            assert!(re.is_match("a\nb\nc"));
+            let re = Regex::new("(?m:^b)$").unwrap().engine(engine).unwrap();
+            assert!(!re.is_match("a\nb\nc"));
+            assert!(re.is_match("a\nb"));
+
+            // .が改行にもマッチ
+            let re = Regex::new("a.b(?s)a.b").unwrap().engine(engine).unwrap();
+            assert!(re.full_match("a-ba\nb"));
+            assert!(!re.full_match("a\nba\nb"));
+            let re = Regex::new("(?s:.)(?-s:.)").unwrap().engine(engine).unwrap();
+            assert!(re.full_match("\na"));
+            assert!(!re.full_match("a\n"));
+
+            // 空白とコメントを無視
+            let re = Regex::new("(?x) a + \\  b # comment\n c [ ]")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert!(re.full_match("aa bc "));
+            let re = Regex::new("(?x: a b )c d").unwrap().engine(engine).unwrap();
+            assert!(re.full_match("abc d"));
+            let re = Regex::new("(?ix)a \\# b").unwrap().engine(engine).unwrap();
+            assert!(re.full_match("A#B"));
+        }
+
+        assert!(Regex::new("(?y)a").is_err());
+        assert!(Regex::new("(?i-)a").is_err());
+        assert!(Regex::new("(?-:a)").is_err());
+        assert!(Regex::new("(?i--m)a").is_err());
+        assert!(Regex::new("(?i:a").is_err());
+    }
+
+    #[test]
+    fn test_dot_matches_new_line() {
+        for engine in ENGINES {
+            let re = Regex::new("a.c").unwrap().engine(engine).unwrap();
+            assert!(!re.is_match("a\nc"));
+            let re = RegexBuilder::new("a.c")
+                .dot_matches_new_line(true)
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(re.is_match("a\nc"));
+            let re = RegexBuilder::new("<p>(.*)</p>")
+                .dot_matches_new_line(true)
+                .engine(engine)
+                .build()
+                .unwrap();
+            let caps = re.captures("<p>one\ntwo</p>").unwrap();
+            assert_eq!(caps.get(1).unwrap().as_str(), "one\ntwo");
+            let re = RegexBuilder::new("a(?-s).c")
+                .dot_matches_new_line(true)
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(!re.is_match("a\nc"));
+        }
+    }
+
+    #[test]
+    fn test_named_group() {
+        for engine in ENGINES {
+            let re = Regex::new("(?P<year>\\d{4})-(?<month>\\d\\d)(?:-(?<day>\\d\\d))?")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert_eq!(re.captures_len(), 4);
+            assert_eq!(re.capture_index("month"), Some(2));
+            assert_eq!(re.capture_index("hour"), None);
+
+            let caps = re.captures("date: 2024-10-15").unwrap();
+            assert_eq!(caps.name("year").unwrap().as_str(), "2024");
+            assert_eq!(caps.name("month").unwrap().range(), 11..13);
+            assert_eq!(caps.name("day").unwrap().as_str(), "15");
+            assert_eq!(caps.get(3), caps.name("day"));
+            assert_eq!(caps.name("hour"), None);
+
+            let caps = re.captures("2024-10").unwrap();
+            assert_eq!(caps.name("month").unwrap().as_str(), "10");
+            assert_eq!(caps.name("day"), None);
+
+            // 名前付きグループの中の番号付きグループ
+            let re = Regex::new("(?<outer>a(b)(?<inner>c))")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            let caps = re.captures("abc").unwrap();
+            assert_eq!(caps.name("outer").unwrap().as_str(), "abc");
+            assert_eq!(caps.get(2).unwrap().as_str(), "b");
+            assert_eq!(caps.name("inner").unwrap().as_str(), "c");
+        }
+
+        let err = |expr: &str| Regex::new(expr).unwrap_err().to_string();
+        assert_eq!(
+            err("(?<a>x)(?P<a>y)"),
+            "ParseError: duplicate group name: pos = 7, name = a"
+        );
+        assert_eq!(err("a(?<1a>x)"), "ParseError: invalid group name: pos = 1");
+        assert_eq!(err("(?<>x)"), "ParseError: invalid group name: pos = 0");
+        assert_eq!(err("(?P=a)"), "ParseError: invalid group name: pos = 0");
+        assert!(Regex::new("(?<a-b>x)").is_err());
+        assert!(Regex::new("(?<a").is_err());
+    }
+
+    #[test]
+    fn test_backref() {
+        let re = Regex::new("(\\w+) \\1").unwrap();
+        assert_eq!(re.find("say hello hello!").unwrap().as_str(), "hello hello");
+        assert!(!re.is_match("hello world"));
+        let re = Regex::new("^(?<q>['\"]).*\\k<q>$").unwrap();
+        assert!(re.is_match("'abc'"));
+        assert!(re.is_match("\"a'c\""));
+        assert!(!re.is_match("'abc\""));
+        let re = Regex::new("^(a|b)*\\1$").unwrap();
+        assert!(re.is_match("abaa"));
+        assert!(!re.is_match("abab"));
+        // マッチに参加していないグループへの後方参照は失敗する
+        let re = Regex::new("^(?:(a)|b)\\1$").unwrap();
+        assert!(re.is_match("aa"));
+        assert!(!re.is_match("b"));
+        let re = Regex::new("(?i)(ab)\\1").unwrap();
+        assert!(re.full_match("abAB"));
+        let re = Regex::new("(?i)(σ)\\1").unwrap();
+        assert!(re.full_match("σΣ"));
+        let re = RegexBuilder::new("(é)\\1")
+            .case_insensitive(true)
+            .unicode(false)
+            .build()
+            .unwrap();
+        assert!(!re.full_match("éÉ"));
+
+        // 後方参照は線形時間の評価器ではサポートしない
+        for engine in [Engine::WidthFirst, Engine::Backtrack] {
+            let err = RegexBuilder::new("(a)\\1")
+                .engine(engine)
+                .build()
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "EvalError: backreferences are not supported by the {:?} engine, use DepthFirst",
+                    engine
+                )
+            );
+            let err = Regex::new("(a)\\1").unwrap().engine(engine).unwrap_err();
+            assert!(err.to_string().starts_with("EvalError: backreferences"));
+        }
+
+        let err = |expr: &str| Regex::new(expr).unwrap_err().to_string();
+        assert_eq!(err("(a)\\2"), "ParseError: invalid backreference: pos = 4");
+        assert_eq!(err("\\1(a)"), "ParseError: invalid backreference: pos = 1");
+        assert_eq!(
+            err("(?<a>x)\\k<b>"),
+            "ParseError: unknown group name: pos = 8, name = b"
+        );
+        assert!(Regex::new("(a)\\k").is_err());
+        assert!(Regex::new("(a)[\\1]").is_err());
+    }
+
+    #[test]
+    fn test_look_around() {
+        for engine in ENGINES {
+            // 先読み
+            let re = Regex::new("\\w+(?=:)").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("a b: c").unwrap().as_str(), "b");
+            let re = Regex::new("^(?=.*\\d)(?=.*[a-z]).{8,}$")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert!(re.is_match("passw0rd"));
+            assert!(!re.is_match("password"));
+            assert!(!re.is_match("pass0"));
+            let re = Regex::new("\\b(?!un)\\w+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("undo redo").unwrap().as_str(), "redo");
+
+            // 後読み
+            let re = Regex::new("(?<=\\$)\\d+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("10 items for $25").unwrap().as_str(), "25");
+            let re = Regex::new("(?<!-)\\b\\d+").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("-3 4").unwrap().as_str(), "4");
+            let re = Regex::new("(?<=a|bc)d").unwrap().engine(engine).unwrap();
+            assert_eq!(re.find("bd bcd ad").unwrap().range(), 5..6);
+            let re = Regex::new("(?<=^|,)\\w{1,2}(?=,|$)")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            assert_eq!(re.find("abc,de,f").unwrap().as_str(), "de");
+
+            // 先読みの中のキャプチャ
+            let re = Regex::new("(?=(\\w+))\\w").unwrap().engine(engine).unwrap();
+            let caps = re.captures("  hello").unwrap();
+            assert_eq!(caps.get(0).unwrap().as_str(), "h");
+            assert_eq!(caps.get(1).unwrap().as_str(), "hello");
+            let re = Regex::new("(?!(a))\\w").unwrap().engine(engine).unwrap();
+            let caps = re.captures("ab").unwrap();
+            assert_eq!(caps.get(0).unwrap().as_str(), "b");
+            assert_eq!(caps.get(1), None);
+        }
+
+        // 部分プログラムも呼び出し元の評価器で評価するため、指数時間にならない
+        for engine in [Engine::WidthFirst, Engine::Backtrack] {
+            let re = RegexBuilder::new("(?=(?:a?){24}a{24}b)")
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(!re.is_match(&"a".repeat(24)));
+            let re = RegexBuilder::new("(?<=(?:a?){12}a{12})b")
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert_eq!(
+                re.find(&format!("{}b", "a".repeat(12))).unwrap().start(),
+                12
+            );
+        }
+
+        let err = |expr: &str| Regex::new(expr).unwrap_err().to_string();
+        assert_eq!(err("a(?<=b+)"), "ParseError: unbounded lookbehind: pos = 1");
+        assert_eq!(
+            err("(?<!a|b*)"),
+            "ParseError: unbounded lookbehind: pos = 0"
+        );
+        assert_eq!(
+            err("(a)(?<=\\1)"),
+            "ParseError: unbounded lookbehind: pos = 3"
+        );
+        assert!(Regex::new("(?<=a{2,5}(?=b))").is_ok());
+        assert!(Regex::new("(?=a").is_err());
+    }
+
+    #[test]
+    fn test_atomic() {
+        // アトミックグループの中には戻らない
+        let re = Regex::new("^(?>a+)ab").unwrap();
+        assert!(!re.is_match("aaab"));
+        let re = Regex::new("^(?>a|ab)c").unwrap();
+        assert!(re.is_match("ac"));
+        assert!(!re.is_match("abc"));
+        let re = Regex::new("(?>(\\w+))-").unwrap();
+        assert_eq!(
+            re.captures("x foo-").unwrap().get(1).unwrap().as_str(),
+            "foo"
+        );
+
+        // 絶対最大量指定子
+        let re = Regex::new("^a*+a").unwrap();
+        assert!(!re.is_match("aaa"));
+        let re = Regex::new("^\"[^\"]*+\"").unwrap();
+        assert!(re.is_match("\"abc\" x"));
+        let re = Regex::new("^a?+a$").unwrap();
+        assert!(re.is_match("aa"));
+        assert!(!re.is_match("a"));
+        let re = Regex::new("^a{1,3}+a$").unwrap();
+        assert!(re.is_match("aaaa"));
+        assert!(!re.is_match("aaa"));
+        let re = Regex::new("^(?:ab)++$").unwrap();
+        assert!(re.is_match("abab"));
+
+        // 破滅的なバックトラックを起こさない
+        let re = Regex::new("^(a++)+b").unwrap();
+        assert!(!re.is_match(&"a".repeat(64)));
+        let re = Regex::new("^(?>(a+)+)b").unwrap();
+        assert!(!re.is_match(&"a".repeat(64)));
+
+        // 後続の命令の成否によらず最初のマッチを採用する
+        let re = Regex::new("^(?:a|)(?>a*)a").unwrap();
+        assert!(!re.is_match("aaa"));
+
+        // アトミックグループは深さ優先探索でのみサポートする
+        for engine in [Engine::WidthFirst, Engine::Backtrack] {
+            let err = RegexBuilder::new("a++").engine(engine).build().unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "EvalError: atomic groups and possessive quantifiers are not supported by the {:?} engine, use DepthFirst",
+                    engine
+                )
+            );
+        }
+        assert!(Regex::new("(?>a").is_err());
+    }
+
+    #[test]
+    fn test_lazy_dfa() {
+        let exprs = [
+            "abc|(?:de|cd)+",
+            "a*b*c*",
+            "^a.c$",
+            "(?s)a.c",
+            "(?m)^b$",
+            "\\bfoo\\b",
+            "\\Bo+\\B",
+            "[a-c]{2,3}x",
+            "(?i)straße",
+            "(?:a*)*b",
+            "a+?b",
+            "\\d+\\s\\w+",
+            "(?:a|b)*a(?:a|b){6}",
+        ];
+        let lines = [
+            "",
+            "abc",
+            "xxdecd",
+            "a\nc",
+            "a\nb\nc",
+            "foo bar",
+            "foobar",
+            "ccx abx",
+            "STRASSE straße",
+            "aaaab",
+            "12 ab",
+            "abbababbbaa",
+            "bbbbbbbbbbbbbbbbabbbbbb",
+        ];
+        for expr in exprs {
+            // 遅延DFAを利用しない場合と結果が一致する
+            let nfa = RegexBuilder::new(expr)
+                .dfa_cache_capacity(0)
+                .build()
+                .unwrap();
+            // キャッシュが小さく、破棄が繰り返される場合はNFAでの評価に切り替わる
+            let small = RegexBuilder::new(expr)
+                .dfa_cache_capacity(2)
+                .build()
+                .unwrap();
+            let dfa = Regex::new(expr).unwrap();
+            for line in lines {
+                for re in [&dfa, &small] {
+                    assert_eq!(re.is_match(line), nfa.is_match(line), "{expr} {line:?}");
+                    assert_eq!(re.full_match(line), nfa.full_match(line), "{expr} {line:?}");
+                    assert_eq!(
+                        re.is_match_at_start(line).unwrap(),
+                        nfa.is_match_at_start(line).unwrap(),
+                        "{expr} {line:?}"
+                    );
+                    assert_eq!(re.find(line), nfa.find(line), "{expr} {line:?}");
+                }
+            }
+        }
+
+        // 遅延DFAで評価できない正規表現は評価器で評価する
+        let re = Regex::new("(?:a)(?=b)").unwrap();
+        assert!(re.is_match("ab"));
+        let re = Regex::new("^a++b").unwrap();
+        assert!(!re.is_match("aaa"));
+    }
+
+    #[test]
+    fn test_dfa() {
+        let exprs = [
+            "abc|(?:de|cd)+",
+            "a*b*c*",
+            "^a.c$",
+            "(?s)a.c",
+            "(?m)^b$",
+            "\\bfoo\\b",
+            "\\Bo+\\B",
+            "[a-c]{2,3}x",
+            "(?i)straße",
+            "(?:a*)*b",
+            "\\d+\\s\\w+",
+            "(?:a|b)*a(?:a|b){6}",
+            "[^\\n]+z",
+            "(x)?y",
+        ];
+        let lines = [
+            "",
+            "abc",
+            "xxdecd",
+            "a\nc",
+            "a\nb\nc",
+            "foo bar",
+            "foobar",
+            "éé",
+            "ccx abx",
+            "STRASSE straße",
+            "aaaab",
+            "12 ab",
+            "abbababbbaa",
+            "bbbbbbbbbbbbbbbbabbbbbb",
+            "a\nz",
+            "\u{10FFFF}y",
+        ];
+        for expr in exprs {
+            // 事前に構築したDFAでの判定は評価器での判定と一致する
+            let nfa = RegexBuilder::new(expr)
+                .dfa_cache_capacity(0)
+                .build()
+                .unwrap();
+            let dfa = RegexBuilder::new(expr).dfa(true).build().unwrap();
+            for line in lines {
+                assert_eq!(dfa.is_match(line), nfa.is_match(line), "{expr} {line:?}");
+                assert_eq!(dfa.find(line), nfa.find(line), "{expr} {line:?}");
+            }
+        }
+
+        let re = RegexBuilder::new("\\bé")
+            .unicode(false)
+            .dfa(true)
+            .build()
+            .unwrap();
+        assert!(re.is_match("aé"));
+        assert!(!re.is_match("é"));
+
+        // 決定化できない正規表現ではDFAを構築しない
+        let re = RegexBuilder::new("(a)\\1").dfa(true).build().unwrap();
+        assert!(re.is_match("aa"));
+
+        let err = RegexBuilder::new("(?:a|b)*a(?:a|b){12}")
+            .dfa(true)
+            .dfa_state_limit(1000)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "DfaError: state limit exceeded: limit = 1000"
+        );
+    }
+
+    #[test]
+    fn test_onepass() {
+        let exprs = [
+            "^key=(\\w+);",
+            "^(a+)(b|c)",
+            "\\Aa(bc)?",
+            "^a(bc)??",
+            "^(\\d+)-(\\d+)",
+            "^(\\w+)\\s(\\w+)$",
+            "^\\b(\\w+)\\b",
+            "^x(?:a|b)*?y",
+            "^(a)|^b",
+            "^(?:(a)|b)*c",
+            "^(x*)(y)",
+            "^(a|ab)c",
+            "(?m)^(\\w+)$",
+            "key=(\\w+);",
+            "(a+)(b|c)",
+            "a(bc)??",
+            "(\\d+)-(\\d+)$",
+            "\\b(\\w+)\\b",
+            "x(?:a|b)*?y",
+            "(a)|b",
+            "(?:(a)|b)*c",
+            "(x*)(y)",
+            "(a|ab)c",
+        ];
+        let lines = [
+            "",
+            "key=abc; key=d;",
+            "xaac",
+            "abcd abx",
+            "12-34-56",
+            "foo bar",
+            "foo bar baz",
+            "a\nbc\n",
+            "xabbay xy",
+            "bbac",
+            "xxy",
+            "abc",
+        ];
+        for expr in exprs {
+            // one-passな評価器での結果は、他の評価器での結果と一致する
+            let re = Regex::new(expr).unwrap();
+            let nfa = RegexBuilder::new(expr).onepass(false).build().unwrap();
+            for line in lines {
+                assert_eq!(re.captures(line), nfa.captures(line), "{expr} {line:?}");
+            }
+        }
+
+        // アンカーされていない正規表現でも、開始位置ごとに評価をやり直さない
+        let line = "a".repeat(20000);
+        for expr in ["(a|b)*c(d)", "^(a|b)*c(d)"] {
+            let re = Regex::new(expr).unwrap();
+            assert!(re.captures(&line).is_none());
+            let matched = format!("{line}cd");
+            let caps = re.captures(&matched).unwrap();
+            assert_eq!(caps.get(0).unwrap().range(), 0..20002);
+            assert_eq!(caps.get(2).unwrap().range(), 20001..20002);
+
+            let re = Regex::new(expr)
+                .unwrap()
+                .engine(Engine::WidthFirst)
+                .unwrap();
+            assert!(re.captures(&line).is_none());
+        }
+    }
+
+    #[test]
+    fn test_bytes() {
+        let exprs = [
+            "abc|(de|cd)+",
+            "é+",
+            "[à-ÿ]+\\d",
+            "\\w+",
+            "(?i)straße",
+            "^.+$",
+            "(?s)a.c",
+            "\\bfoo\\b",
+            "(?<=é)x",
+            "[\\x7F-\\u{10000}]{2}",
+            "[^a]",
+            "\\B",
+            "(?=\\B)",
+        ];
+        let lines = [
+            "",
+            "xxdecd",
+            "café2 ÿ9",
+            "日本語 text",
+            "STRASSE straẞe",
+            "a\nc",
+            "éfoo foo",
+            "éx",
+            "é",
+            "\u{7F}\u{800}\u{FFFF}\u{10000}\u{10FFFF}",
+        ];
+        for engine in ENGINES {
+            for expr in exprs {
+                // 有効なUTF-8の入力では、文字単位の評価と同じ範囲にマッチする
+                let re = Regex::new(expr).unwrap().engine(engine).unwrap();
+                let bytes = BytesRegex::new(expr).unwrap().engine(engine).unwrap();
+                for line in lines {
+                    assert_eq!(
+                        bytes.find(line.as_bytes()).map(|m| m.range()),
+                        re.find(line).map(|m| m.range()),
+                        "{engine:?} {expr} {line:?}"
+                    );
+                }
+            }
+
+            // 不正なUTF-8を含む入力も探索できる
+            let re = BytesRegex::new("error: (\\w+)")
+                .unwrap()
+                .engine(engine)
+                .unwrap();
+            let m = re.find(b"\x00\xFF\xFEerror: disk\xC0").unwrap();
+            assert_eq!(m.as_bytes(), b"error: disk");
+            assert_eq!((m.start(), m.end()), (3, 14));
+
+            // 不正なバイトはどの文字にもマッチしない
+            let re = BytesRegex::new("^(?s).$").unwrap().engine(engine).unwrap();
+            assert!(re.is_match("\u{10FFFF}".as_bytes()));
+            assert!(!re.is_match(b"\xFF"));
+            assert!(!re.is_match(b"\xED\xA0\x80"));
+            let re = BytesRegex::new("a[^b]c").unwrap().engine(engine).unwrap();
+            assert!(!re.is_match(b"a\x80c"));
+            assert!(re.is_match_at_start(b"a\xC3\xA9c").unwrap());
+        }
+
+        let re = BytesRegex::new("(?i)(\\w+) \\1").unwrap();
+        assert_eq!(
+            re.find("x straße STRAẞE".as_bytes()).unwrap().range(),
+            2..18
+        );
+        assert!(re.is_match(b"\xFF\xFF ab AB"));
+        assert!(!re.is_match(b"ab \xFF"));
+
+        assert!(do_matching_bytes("a(bc)+", b"abcbc\xFF", true).unwrap());
+        assert!(!do_matching_bytes(".", b"\xFF", false).unwrap());
+    }
 }